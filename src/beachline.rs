@@ -2,14 +2,15 @@ extern crate slab;
 use std::ops::{Index, IndexMut};
 use std::cmp::Ordering;
 use crate::Site;
+use crate::error::{Result, invariant};
 use crate::treeprint::print;
 
 use slab::Slab;
 
 #[derive(Eq, PartialEq, Debug, Clone, Copy)]
 enum Color {
-    RED,
-    BLACK
+    Red,
+    Black
 }
 
 #[derive(Eq, PartialEq, Copy, Clone, Debug, Hash)]
@@ -78,18 +79,19 @@ impl BeachLine {
         }
     }
 
-    pub fn init(&mut self, value: Site) {
+    pub fn init(&mut self, value: Site) -> BeachSegmentHandle {
         if !self.root.is_null() {
             panic!("Tried initializing a non-empty beachline");
         }
         self.root = Pointer(self.nodes.insert(Node {
-            color: Color::BLACK,
+            color: Color::Black,
             parent: Pointer::null(),
             left: Pointer::null(),
             right: Pointer::null(),
-            value: value
+            value
         }));
         self.insert_repair(self.root);
+        BeachSegmentHandle(self.root)
     }
 
     pub fn get(&self, handle: BeachSegmentHandle) -> &Site {
@@ -141,12 +143,12 @@ impl BeachLine {
                 }
             }
         }
-        return BeachSegmentHandle(Pointer::null());
+        BeachSegmentHandle(Pointer::null())
     }
 
-    pub fn delete(&mut self, handle: BeachSegmentHandle) -> Option<Site> {
+    pub fn delete(&mut self, handle: BeachSegmentHandle) -> Result<Site> {
         let at = handle.0;
-        if at.is_null() { return None; }
+        if at.is_null() { return invariant(String::from("tried deleting a null beach segment")); }
 
         if !self[at].left.is_null() && !self[at].right.is_null() {
             // Node has two children,
@@ -159,11 +161,11 @@ impl BeachLine {
             let parent = self[at].parent;
             if !parent.is_null() {
                 // Node is not root, so properly update its parent
-                if self[at].color == Color::BLACK {
+                if self[at].color == Color::Black {
                     // We're gonna end up invalidating the RB tree, repair it
                     // Note that if either parent or node are red, we end up with
                     // just a black node which is a valid replacement
-                    self.delete_repair(at);
+                    self.delete_repair(at)?;
                 }
 
                 let parent = self[at].parent;
@@ -176,7 +178,7 @@ impl BeachLine {
                 self.root = Pointer::null();
             }
             let node = self.nodes.remove(at.0);
-            return Some(node.value);
+            return Ok(node.value);
         }
 
         // Node has exactly one child
@@ -195,30 +197,30 @@ impl BeachLine {
 
         // Repair at child
         let node = self.nodes.remove(at.0);
-        if node.color == Color::RED {
+        if node.color == Color::Red {
             // Nothing to repair, tree still valid
         } else {
-            if self[child].color == Color::RED {
+            if self[child].color == Color::Red {
                 // Just set the child color to black and we're still good
-                self[child].color = Color::BLACK;
+                self[child].color = Color::Black;
             } else {
                 // Node and child were both black
                 // This is actually impossible. It would mean that the path from
                 // the "leaf" on one side of this node would have fewer black
                 // nodes than the path from the leaves on the other side.
-                panic!("Impossible case: deleting black node with one black child");
+                return invariant(format!("deleting black node for site {} with one black child", node.value.id));
             }
         }
 
-        return Some(node.value);
+        Ok(node.value)
     }
 
     pub fn predecessor(&self, handle: BeachSegmentHandle) -> BeachSegmentHandle {
-        return BeachSegmentHandle(self.predecessor_inner(handle.0));
+        BeachSegmentHandle(self.predecessor_inner(handle.0))
     }
 
     pub fn successor(&self, handle: BeachSegmentHandle) -> BeachSegmentHandle {
-        return BeachSegmentHandle(self.successor_inner(handle.0));
+        BeachSegmentHandle(self.successor_inner(handle.0))
     }
 
     fn predecessor_inner(&self, at: Pointer) -> Pointer {
@@ -241,13 +243,13 @@ impl BeachLine {
                 child = parent;
                 parent = self[parent].parent;
             }
-            return parent;
+            parent
         } else {
             let mut child = self[at].left;
             while !self[child].right.is_null() {
                 child = self[child].right;
             }
-            return child;
+            child
         }
     }
 
@@ -271,13 +273,13 @@ impl BeachLine {
                 child = parent;
                 parent = self[parent].parent;
             }
-            return parent;
+            parent
         } else {
             let mut child = self[at].right;
             while !self[child].left.is_null() {
                 child = self[child].left;
             }
-            return child;
+            child
         }
     }
 
@@ -363,11 +365,11 @@ impl BeachLine {
 
     fn create_node(value: Site, parent: Pointer) -> Node {
         Node {
-            color: Color::RED,
-            parent: parent,
+            color: Color::Red,
+            parent,
             left: Pointer::null(),
             right: Pointer::null(),
-            value: value
+            value
         }
     }
 
@@ -376,16 +378,16 @@ impl BeachLine {
 
         if self[at].parent.is_null() {
             // Repair case 1
-            self[at].color = Color::BLACK;
-        } else if self[self[at].parent].color == Color::BLACK {
+            self[at].color = Color::Black;
+        } else if self[self[at].parent].color == Color::Black {
             // Nothing to do, we're fine
-        } else if uncle != Pointer::null() && self[uncle].color == Color::RED {
+        } else if uncle != Pointer::null() && self[uncle].color == Color::Red {
             // Change uncle and parent to black
             let parent = self[at].parent;
             let grandparent = self[parent].parent;
-            self[uncle].color = Color::BLACK;
-            self[parent].color = Color::BLACK;
-            self[grandparent].color = Color::RED;
+            self[uncle].color = Color::Black;
+            self[parent].color = Color::Black;
+            self[grandparent].color = Color::Red;
             self.insert_repair(grandparent)
         } else {
             // Note: grandparent must exist because otherwise parent would be black
@@ -410,20 +412,22 @@ impl BeachLine {
             } else {
                 self.rotate_left(grandparent);
             }
-            self[parent].color = Color::BLACK;
-            self[grandparent].color = Color::RED;
+            self[parent].color = Color::Black;
+            self[grandparent].color = Color::Red;
         }
     }
 
-    fn delete_repair(&mut self, at: Pointer) {
+    fn delete_repair(&mut self, at: Pointer) -> Result<()> {
         // Precondition: node is black and has one fewer black nodes on its path
         // to the root than its sibling does. So we need to either add a black
         // node to the node's paths or we need to take one away from the sibling's
         // paths (in which case we need to recurse upwards).
-        assert!(self[at].color == Color::BLACK);
+        if self[at].color != Color::Black {
+            return invariant(format!("repairing deletion at red node for site {}", self[at].value.id));
+        }
         if self.root == at {
             // Case 1: root is black and stays black, not a problem
-            return;
+            return Ok(());
         }
 
         let mut sibling = self.sibling(at);
@@ -432,15 +436,15 @@ impl BeachLine {
 
         if sibling.is_null() {
             // Impossible, black nodes never have null siblings
-            panic!("Black node has a null sibling");
+            return invariant(format!("black node for site {} has a null sibling", self[at].value.id));
         }
 
-        if self[sibling].color == Color::RED {
+        if self[sibling].color == Color::Red {
             // Case 2: switch parent's and sibling's colors and rotate around
             // parent. The result is that node's parent is red, and we can proceed
             // to cases 4, 5, and 6.
-            self[sibling].color = Color::BLACK;
-            self[parent].color = Color::RED;
+            self[sibling].color = Color::Black;
+            self[parent].color = Color::Red;
             if is_left {
                 self.rotate_left(parent);
             } else {
@@ -449,38 +453,38 @@ impl BeachLine {
             sibling = self.sibling(at);
             parent = self[at].parent;
         } else if
-            self[sibling].color == Color::BLACK &&
-            self[parent].color == Color::BLACK &&
+            self[sibling].color == Color::Black &&
+            self[parent].color == Color::Black &&
             self.has_black_children(sibling)
         {
             // Case 3: we can balance the parent tree by setting sibling to RED,
             // but we may invalidate the tree above parent, so recursively call
             // delete_repair
-            self[sibling].color = Color::RED;
+            self[sibling].color = Color::Red;
             return self.delete_repair(parent);
         }
 
         if
-            self[parent].color == Color::RED &&
-            self[sibling].color == Color::BLACK &&
+            self[parent].color == Color::Red &&
+            self[sibling].color == Color::Black &&
             self.has_black_children(sibling)
         {
             // Case 4: easy--we can just swap the colors of parent and sibling,
             // which adds one black node to all of this node's paths and doesn't
             // affect sibling's paths
-            self[sibling].color = Color::RED;
-            self[parent].color = Color::BLACK;
-            return;
+            self[sibling].color = Color::Red;
+            self[parent].color = Color::Black;
+            return Ok(());
         }
 
         if
-            self[sibling].color == Color::BLACK &&
+            self[sibling].color == Color::Black &&
             (is_left && (
-                (!self[sibling].left.is_null() && self[self[sibling].left].color == Color::RED) &&
-                (self[sibling].right.is_null() || self[self[sibling].right].color == Color::BLACK)
+                (!self[sibling].left.is_null() && self[self[sibling].left].color == Color::Red) &&
+                (self[sibling].right.is_null() || self[self[sibling].right].color == Color::Black)
             )) || (!is_left && (
-                (!self[sibling].right.is_null() && self[self[sibling].right].color == Color::RED) &&
-                (self[sibling].left.is_null() || self[self[sibling].left].color == Color::BLACK)
+                (!self[sibling].right.is_null() && self[self[sibling].right].color == Color::Red) &&
+                (self[sibling].left.is_null() || self[self[sibling].left].color == Color::Black)
             ))
         {
             // Case 5 (depends on is_left, which we assume is true in the comment)
@@ -490,13 +494,13 @@ impl BeachLine {
             // in case 6
             if is_left {
                 let left = self[sibling].left;
-                self[sibling].color = Color::RED;
-                self[left].color = Color::BLACK;
+                self[sibling].color = Color::Red;
+                self[left].color = Color::Black;
                 self.rotate_right(sibling);
             } else {
                 let right = self[sibling].right;
-                self[sibling].color = Color::RED;
-                self[right].color = Color::BLACK;
+                self[sibling].color = Color::Red;
+                self[right].color = Color::Black;
                 self.rotate_left(sibling);
             }
             sibling = self.sibling(at);
@@ -509,28 +513,28 @@ impl BeachLine {
         // node have one additional black ancestor and paths through sibling have
         // the same number as before.
         self[sibling].color = self[parent].color;
-        self[parent].color = Color::BLACK;
+        self[parent].color = Color::Black;
         if is_left {
             let sibling_right = self[sibling].right;
             if !sibling_right.is_null() {
                 // Note: null is already black
-                self[sibling_right].color = Color::BLACK;
+                self[sibling_right].color = Color::Black;
             }
             self.rotate_left(parent);
         } else {
             let sibling_left = self[sibling].left;
             if !sibling_left.is_null() {
                 // Note: null is already black
-                self[sibling_left].color = Color::BLACK;
+                self[sibling_left].color = Color::Black;
             }
             self.rotate_right(parent);
         }
+        Ok(())
     }
 
     fn has_black_children(&self, at: Pointer) -> bool {
-        return
-            (self[at].left.is_null() || self[self[at].left].color == Color::BLACK) &&
-            (self[at].right.is_null() || self[self[at].right].color == Color::BLACK);
+        (self[at].left.is_null() || self[self[at].left].color == Color::Black) &&
+            (self[at].right.is_null() || self[self[at].right].color == Color::Black)
     }
 
     fn sibling(&self, at: Pointer) -> Pointer {
@@ -539,9 +543,9 @@ impl BeachLine {
         }
         let is_right = self[self[at].parent].right == at;
         if is_right {
-            return self[self[at].parent].left;
+            self[self[at].parent].left
         } else {
-            return self[self[at].parent].right;
+            self[self[at].parent].right
         }
     }
 
@@ -550,7 +554,7 @@ impl BeachLine {
         if self[at].parent.is_null() {
             return Pointer::null();
         }
-        return self.sibling(self[at].parent);
+        self.sibling(self[at].parent)
     }
 
     fn rotate_left(&mut self, at: Pointer) {
@@ -606,8 +610,8 @@ impl BeachLine {
     }
 
     #[allow(dead_code)]
-    pub fn in_order<F>(&self, mut f: F) where F: FnMut(&Site) -> () {
-        fn in_order_at<F>(tree: &BeachLine, f: &mut F, at: Pointer) where F: FnMut(&Site) -> () {
+    pub fn in_order<F>(&self, mut f: F) where F: FnMut(&Site) {
+        fn in_order_at<F>(tree: &BeachLine, f: &mut F, at: Pointer) where F: FnMut(&Site) {
             if at.is_null() {
                 return;
             }
@@ -646,8 +650,8 @@ impl BeachLine {
         }, |at| {
             let node = &self[*at];
             match node.color {
-                Color::BLACK => format!("B:{}", node.value.id),
-                Color::RED => format!("R:{}", node.value.id),
+                Color::Black => format!("B:{}", node.value.id),
+                Color::Red => format!("R:{}", node.value.id),
             }
         });
     }
@@ -0,0 +1,892 @@
+// Regions that a diagram can be clipped to. The clipping itself happens in
+// `Dcel::bound`, which only needs to know how edges cross a region and how to
+// walk along its boundary between the points where a cell leaves and re-enters
+// it.
+
+use crate::error::{Result, VoronoiError};
+use crate::predicates::orient2d;
+use std::cmp::Ordering;
+
+// The points x + t * dx, y + t * dy for t_min <= t <= t_max
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Line {
+    pub x: f64,
+    pub y: f64,
+    pub dx: f64,
+    pub dy: f64,
+    pub t_min: f64,
+    pub t_max: f64
+}
+
+impl Line {
+    // Returns the part of the line inside a region as a range of t, or None if
+    // the line misses the region
+    pub(crate) fn clip<R: Boundary + ?Sized>(&self, region: &R) -> Option<(f64, f64)> {
+        region.clip_line((self.x, self.y), (self.dx, self.dy), (self.t_min, self.t_max))
+    }
+}
+
+// A region to clip cells to. Clipping depends on the details of each region's
+// boundary (see `Boundary`), so only the regions in this module implement it.
+pub trait ClipRegion: Boundary {
+    // Checks that the region is something cells can be clipped to
+    fn validate(&self) -> Result<()>;
+
+    fn contains(&self, x: f64, y: f64) -> bool;
+}
+
+// What the clipping needs to know about the boundary of a region. This isn't
+// exported, which seals `ClipRegion`. Convex regions have their boundary walked
+// counterclockwise, and points on it are identified by their distance along it
+// from the first corner. Other regions are described by their rings.
+pub trait Boundary {
+    // Clips the line through start along direction, for t in range, see
+    // `Line::clip`. The line is passed in parts so that `Line` stays private.
+    fn clip_line(&self, start: (f64, f64), direction: (f64, f64), range: (f64, f64)) -> Option<(f64, f64)>;
+
+    // Any point strictly inside the region
+    fn interior_point(&self) -> (f64, f64);
+
+    // Points closer than this to the boundary are considered to be on it
+    fn tolerance(&self) -> f64;
+
+    // Moves a point that's slightly outside the region onto its boundary
+    fn clamp(&self, x: f64, y: f64) -> (f64, f64);
+
+    // Whether the segment between two points runs along the boundary
+    fn is_along_boundary(&self, x1: f64, y1: f64, x2: f64, y2: f64) -> bool;
+
+    fn perimeter(&self) -> f64;
+
+    // Distance from the first corner to a point on the boundary, going
+    // counterclockwise
+    fn boundary_position(&self, x: f64, y: f64) -> f64;
+
+    // The corners of the boundary in counterclockwise order
+    fn corners(&self) -> Vec<(f64, f64)>;
+
+    // The center and radius of a region bounded by a circle. Its boundary is
+    // then made of arcs rather than straight lines between corners.
+    fn arc(&self) -> Option<(f64, f64, f64)> {
+        None
+    }
+
+    // The rings of a region that isn't convex, such as a polygon with holes,
+    // each oriented so that the region is on its left. Cells are then cut
+    // against every ring instead of being walked around a single boundary,
+    // so they can end up in several parts.
+    fn rings(&self) -> Option<&[Vec<(f64, f64)>]> {
+        None
+    }
+
+    // Returns the corners passed when walking counterclockwise along the
+    // boundary from one point on it to another
+    fn corners_between(&self, x1: f64, y1: f64, x2: f64, y2: f64) -> Vec<(f64, f64)> {
+        let perimeter = self.perimeter();
+        let tolerance = self.tolerance();
+        let from = self.boundary_position(x1, y1);
+        let distance = (self.boundary_position(x2, y2) - from).rem_euclid(perimeter);
+        // Corners that are about as far as either point would only add a
+        // zero-length piece of boundary
+        let mut passed: Vec<(f64, (f64, f64))> = self.corners().iter()
+            .map(|(x, y)| ((self.boundary_position(*x, *y) - from).rem_euclid(perimeter), (*x, *y)))
+            .filter(|(offset, _)| *offset > tolerance && *offset < distance - tolerance)
+            .collect();
+        passed.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal));
+        passed.iter().map(|(_, corner)| *corner).collect()
+    }
+}
+
+// Restricts the line to p * t <= q for each (p, q), returning the remaining
+// range of t (Liang-Barsky)
+fn clip_to_half_planes(line: &Line, constraints: &[(f64, f64)]) -> Option<(f64, f64)> {
+    let mut t_start = line.t_min;
+    let mut t_end = line.t_max;
+    for (p, q) in constraints.iter() {
+        if *p == 0. {
+            // Parallel to this side, so either always inside or never
+            if *q < 0. {
+                return None;
+            }
+            continue;
+        }
+        let t = q / p;
+        if *p < 0. {
+            // Entering through this side
+            if t > t_start {
+                t_start = t;
+            }
+        } else if t < t_end {
+            // Leaving through this side
+            t_end = t;
+        }
+    }
+    if t_start < t_end { Some((t_start, t_end)) } else { None }
+}
+
+// Twice the area of a polygon, positive if it's counterclockwise
+pub(crate) fn signed_area(points: &[(f64, f64)]) -> f64 {
+    let mut area = 0.;
+    for (i, (x1, y1)) in points.iter().enumerate() {
+        let (x2, y2) = points[(i + 1) % points.len()];
+        area += x1 * y2 - x2 * y1;
+    }
+    area
+}
+
+// The point on the segment from a to b that's nearest to the given point
+pub(crate) fn nearest_on_segment(x: f64, y: f64, (x1, y1): (f64, f64), (x2, y2): (f64, f64)) -> (f64, f64) {
+    let (dx, dy) = (x2 - x1, y2 - y1);
+    let length = dx * dx + dy * dy;
+    if length == 0. {
+        return (x1, y1);
+    }
+    let t = (((x - x1) * dx + (y - y1) * dy) / length).clamp(0., 1.);
+    (x1 + t * dx, y1 + t * dy)
+}
+
+pub(crate) fn distance_to_segment(x: f64, y: f64, a: (f64, f64), b: (f64, f64)) -> f64 {
+    let (nearest_x, nearest_y) = nearest_on_segment(x, y, a, b);
+    (nearest_x - x).hypot(nearest_y - y)
+}
+
+// Whether a point is inside a ring, by counting how often a ray from it to the
+// right crosses the ring
+pub(crate) fn ring_contains(ring: &[(f64, f64)], x: f64, y: f64) -> bool {
+    let mut inside = false;
+    for (i, (x1, y1)) in ring.iter().enumerate() {
+        let (x2, y2) = ring[(i + 1) % ring.len()];
+        if (*y1 > y) != (y2 > y) && x < x1 + (y - y1) / (y2 - y1) * (x2 - x1) {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+// The sides of a region's rings, bucketed by a grid over the region so that
+// only the sides near a point or a segment need to be looked at. Each side is
+// in every square that its box (widened by the tolerance) overlaps.
+pub(crate) struct SideGrid {
+    sides: Vec<((f64, f64), (f64, f64))>,
+    squares: Vec<Vec<usize>>,
+    columns: usize,
+    rows: usize,
+    origin: (f64, f64),
+    size: (f64, f64),
+    tolerance: f64
+}
+
+impl SideGrid {
+    pub(crate) fn new(sides: Vec<((f64, f64), (f64, f64))>, tolerance: f64) -> SideGrid {
+        let (mut min_x, mut min_y, mut max_x, mut max_y) = (f64::INFINITY, f64::INFINITY, -f64::INFINITY, -f64::INFINITY);
+        for (a, b) in &sides {
+            min_x = min_x.min(a.0).min(b.0);
+            min_y = min_y.min(a.1).min(b.1);
+            max_x = max_x.max(a.0).max(b.0);
+            max_y = max_y.max(a.1).max(b.1);
+        }
+        // About one side per square
+        let side = (sides.len() as f64).sqrt().ceil().max(1.) as usize;
+        let mut grid = SideGrid {
+            sides: Vec::new(),
+            squares: vec![Vec::new(); side * side],
+            columns: side,
+            rows: side,
+            origin: (min_x, min_y),
+            size: ((max_x - min_x) / side as f64, (max_y - min_y) / side as f64),
+            tolerance
+        };
+        for (i, (a, b)) in sides.iter().enumerate() {
+            let (columns, rows) = grid.squares_over((a.0.min(b.0), a.1.min(b.1)), (a.0.max(b.0), a.1.max(b.1)));
+            for row in rows {
+                for column in columns.clone() {
+                    grid.squares[row * grid.columns + column].push(i);
+                }
+            }
+        }
+        grid.sides = sides;
+        grid
+    }
+
+    pub(crate) fn side(&self, side: usize) -> ((f64, f64), (f64, f64)) {
+        self.sides[side]
+    }
+
+    // The sides that might be within the tolerance of a box, in order
+    pub(crate) fn near(&self, min: (f64, f64), max: (f64, f64)) -> Vec<usize> {
+        let (columns, rows) = self.squares_over(min, max);
+        let mut near = Vec::new();
+        for row in rows {
+            for column in columns.clone() {
+                near.extend(&self.squares[row * self.columns + column]);
+            }
+        }
+        near.sort_unstable();
+        near.dedup();
+        near
+    }
+
+    // Even-odd rule over all the rings, like `ring_contains`, counting only
+    // the sides in the squares to the right of the point
+    pub(crate) fn contains(&self, x: f64, y: f64) -> bool {
+        let (columns, rows) = self.squares_over((x, y), (f64::INFINITY, y));
+        let mut sides: Vec<usize> = rows.flat_map(|row| {
+            columns.clone().flat_map(move |column| self.squares[row * self.columns + column].iter().copied())
+        }).collect();
+        sides.sort_unstable();
+        sides.dedup();
+        sides.into_iter().filter(|side| {
+            let ((x1, y1), (x2, y2)) = self.sides[*side];
+            (y1 > y) != (y2 > y) && x < x1 + (y - y1) / (y2 - y1) * (x2 - x1)
+        }).count() % 2 == 1
+    }
+
+    // The columns and rows of the squares that a box overlaps, widened by the
+    // tolerance, with anything past the grid going to the squares at its edge
+    fn squares_over(&self, min: (f64, f64), max: (f64, f64)) -> (std::ops::Range<usize>, std::ops::Range<usize>) {
+        let square = |value: f64, origin: f64, size: f64, count: usize| {
+            if size > 0. { (((value - origin) / size).max(0.) as usize).min(count - 1) } else { 0 }
+        };
+        let first_column = square(min.0 - self.tolerance, self.origin.0, self.size.0, self.columns);
+        let last_column = square(max.0 + self.tolerance, self.origin.0, self.size.0, self.columns);
+        let first_row = square(min.1 - self.tolerance, self.origin.1, self.size.1, self.rows);
+        let last_row = square(max.1 + self.tolerance, self.origin.1, self.size.1, self.rows);
+        (first_column..last_column + 1, first_row..last_row + 1)
+    }
+}
+
+// Rounding error that's plausible for points near a region of this size and
+// this far from the origin
+pub(crate) fn tolerance_for(size: f64, magnitude: f64) -> f64 {
+    1e-10 * size + 8. * f64::EPSILON * magnitude
+}
+
+pub struct BoundingBox {
+    min_x: f64,
+    min_y: f64,
+    max_x: f64,
+    max_y: f64
+}
+
+impl BoundingBox {
+    pub fn new(min_x: f64, min_y: f64, max_x: f64, max_y: f64) -> BoundingBox {
+        BoundingBox {
+            min_x,
+            min_y,
+            max_x,
+            max_y
+        }
+    }
+
+    pub fn width(&self) -> f64 {
+        self.max_x - self.min_x
+    }
+
+    pub fn height(&self) -> f64 {
+        self.max_y - self.min_y
+    }
+
+    pub fn mid_x(&self) -> f64 {
+        (self.max_x + self.min_x) / 2.
+    }
+
+    pub fn mid_y(&self) -> f64 {
+        (self.max_y + self.min_y) / 2.
+    }
+}
+
+impl ClipRegion for BoundingBox {
+    fn validate(&self) -> Result<()> {
+        let is_finite = self.width().is_finite() && self.height().is_finite();
+        if !is_finite || self.width() <= 0. || self.height() <= 0. {
+            return Err(VoronoiError::DegenerateConfiguration("bounding box must have a finite, positive area"));
+        }
+        Ok(())
+    }
+
+    fn contains(&self, x: f64, y: f64) -> bool {
+        x >= self.min_x && x <= self.max_x && y >= self.min_y && y <= self.max_y
+    }
+}
+
+impl Boundary for BoundingBox {
+    fn clip_line(&self, (x, y): (f64, f64), (dx, dy): (f64, f64), (t_min, t_max): (f64, f64)) -> Option<(f64, f64)> {
+        let line = &Line { x, y, dx, dy, t_min, t_max };
+        clip_to_half_planes(line, &[
+            (-line.dx, line.x - self.min_x),
+            (line.dx, self.max_x - line.x),
+            (-line.dy, line.y - self.min_y),
+            (line.dy, self.max_y - line.y)
+        ])
+    }
+
+    fn interior_point(&self) -> (f64, f64) {
+        (self.mid_x(), self.mid_y())
+    }
+
+    fn tolerance(&self) -> f64 {
+        let magnitude = self.min_x.abs().max(self.max_x.abs()).max(self.min_y.abs()).max(self.max_y.abs());
+        tolerance_for(self.width().max(self.height()), magnitude)
+    }
+
+    fn clamp(&self, x: f64, y: f64) -> (f64, f64) {
+        (x.max(self.min_x).min(self.max_x), y.max(self.min_y).min(self.max_y))
+    }
+
+    fn is_along_boundary(&self, x1: f64, y1: f64, x2: f64, y2: f64) -> bool {
+        let tolerance = self.tolerance();
+        let near = |a: f64, b: f64, side: f64| (a - side).abs() <= tolerance && (b - side).abs() <= tolerance;
+        near(x1, x2, self.min_x) || near(x1, x2, self.max_x) || near(y1, y2, self.min_y) || near(y1, y2, self.max_y)
+    }
+
+    fn perimeter(&self) -> f64 {
+        2. * (self.width() + self.height())
+    }
+
+    // Starts at the bottom left corner
+    fn boundary_position(&self, x: f64, y: f64) -> f64 {
+        let (width, height) = (self.width(), self.height());
+        let distances = [y - self.min_y, self.max_x - x, self.max_y - y, x - self.min_x];
+        let mut side = 0;
+        for i in 1..4 {
+            if distances[i].abs() < distances[side].abs() {
+                side = i;
+            }
+        }
+        match side {
+            0 => x - self.min_x,
+            1 => width + y - self.min_y,
+            2 => width + height + self.max_x - x,
+            _ => 2. * width + height + self.max_y - y
+        }
+    }
+
+    fn corners(&self) -> Vec<(f64, f64)> {
+        vec![(self.min_x, self.min_y), (self.max_x, self.min_y), (self.max_x, self.max_y), (self.min_x, self.max_y)]
+    }
+}
+
+// A convex polygon, such as a rotated rectangle or a hexagon
+pub struct ConvexPolygon {
+    // Counterclockwise
+    points: Vec<(f64, f64)>,
+    // Distance along the boundary to each point
+    positions: Vec<f64>,
+    perimeter: f64
+}
+
+impl ConvexPolygon {
+    // The corners can be given in either order
+    pub fn new(mut points: Vec<(f64, f64)>) -> ConvexPolygon {
+        if signed_area(&points) < 0. {
+            points.reverse();
+        }
+
+        let mut positions = Vec::with_capacity(points.len());
+        let mut perimeter = 0.;
+        for (i, (x1, y1)) in points.iter().enumerate() {
+            let (x2, y2) = points[(i + 1) % points.len()];
+            positions.push(perimeter);
+            perimeter += (x2 - x1).hypot(y2 - y1);
+        }
+        ConvexPolygon { points, positions, perimeter }
+    }
+
+    fn side(&self, i: usize) -> ((f64, f64), (f64, f64)) {
+        (self.points[i], self.points[(i + 1) % self.points.len()])
+    }
+
+    // Returns the side nearest to a point, and the point on it nearest to the
+    // given point
+    fn nearest_side(&self, x: f64, y: f64) -> (usize, (f64, f64)) {
+        let mut nearest = (0, (x, y));
+        let mut nearest_distance = f64::INFINITY;
+        for i in 0..self.points.len() {
+            let (a, b) = self.side(i);
+            let point = nearest_on_segment(x, y, a, b);
+            let distance = (point.0 - x).hypot(point.1 - y);
+            if distance < nearest_distance {
+                nearest = (i, point);
+                nearest_distance = distance;
+            }
+        }
+        nearest
+    }
+}
+
+impl ClipRegion for ConvexPolygon {
+    fn validate(&self) -> Result<()> {
+        let finite = self.points.iter().all(|(x, y)| x.is_finite() && y.is_finite());
+        if self.points.len() < 3 || !finite || !self.perimeter.is_finite() {
+            return Err(VoronoiError::DegenerateConfiguration("clip polygon must have at least three finite corners"));
+        }
+        // Every corner has to be strictly on the inside of every side that it
+        // isn't on, which also rules out polygons that wind around twice
+        for i in 0..self.points.len() {
+            let (a, b) = self.side(i);
+            for (j, point) in self.points.iter().enumerate() {
+                if j != i && j != (i + 1) % self.points.len() && orient2d(a, b, *point) <= 0. {
+                    return Err(VoronoiError::DegenerateConfiguration("clip polygon must be strictly convex"));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn contains(&self, x: f64, y: f64) -> bool {
+        (0..self.points.len()).all(|i| {
+            let (a, b) = self.side(i);
+            orient2d(a, b, (x, y)) >= 0.
+        })
+    }
+}
+
+impl Boundary for ConvexPolygon {
+    fn clip_line(&self, (x, y): (f64, f64), (dx, dy): (f64, f64), (t_min, t_max): (f64, f64)) -> Option<(f64, f64)> {
+        let line = &Line { x, y, dx, dy, t_min, t_max };
+        // The line is inside a side from a to b where the cross product of the
+        // side and the vector from a to the point is positive
+        let constraints: Vec<(f64, f64)> = (0..self.points.len()).map(|i| {
+            let ((x1, y1), (x2, y2)) = self.side(i);
+            let (side_x, side_y) = (x2 - x1, y2 - y1);
+            let p = -(side_x * line.dy - side_y * line.dx);
+            let q = side_x * (line.y - y1) - side_y * (line.x - x1);
+            (p, q)
+        }).collect();
+        clip_to_half_planes(line, &constraints)
+    }
+
+    fn interior_point(&self) -> (f64, f64) {
+        let count = self.points.len() as f64;
+        let (sum_x, sum_y) = self.points.iter().fold((0., 0.), |(sum_x, sum_y), (x, y)| (sum_x + x, sum_y + y));
+        (sum_x / count, sum_y / count)
+    }
+
+    fn tolerance(&self) -> f64 {
+        let magnitude = self.points.iter().fold(0., |magnitude: f64, (x, y)| magnitude.max(x.abs()).max(y.abs()));
+        tolerance_for(self.perimeter, magnitude)
+    }
+
+    fn clamp(&self, x: f64, y: f64) -> (f64, f64) {
+        if self.contains(x, y) {
+            return (x, y);
+        }
+        self.nearest_side(x, y).1
+    }
+
+    fn is_along_boundary(&self, x1: f64, y1: f64, x2: f64, y2: f64) -> bool {
+        let tolerance = self.tolerance();
+        (0..self.points.len()).any(|i| {
+            let (a, b) = self.side(i);
+            distance_to_segment(x1, y1, a, b) <= tolerance && distance_to_segment(x2, y2, a, b) <= tolerance
+        })
+    }
+
+    fn perimeter(&self) -> f64 {
+        self.perimeter
+    }
+
+    fn boundary_position(&self, x: f64, y: f64) -> f64 {
+        let (side, (nearest_x, nearest_y)) = self.nearest_side(x, y);
+        let (corner_x, corner_y) = self.points[side];
+        self.positions[side] + (nearest_x - corner_x).hypot(nearest_y - corner_y)
+    }
+
+    fn corners(&self) -> Vec<(f64, f64)> {
+        self.points.clone()
+    }
+}
+
+pub struct Circle {
+    x: f64,
+    y: f64,
+    radius: f64
+}
+
+impl Circle {
+    pub fn new(x: f64, y: f64, radius: f64) -> Circle {
+        Circle { x, y, radius }
+    }
+}
+
+impl ClipRegion for Circle {
+    fn validate(&self) -> Result<()> {
+        let is_finite = self.x.is_finite() && self.y.is_finite() && self.radius.is_finite();
+        if !is_finite || self.radius <= 0. {
+            return Err(VoronoiError::DegenerateConfiguration("clip circle must have a finite, positive radius"));
+        }
+        Ok(())
+    }
+
+    fn contains(&self, x: f64, y: f64) -> bool {
+        (x - self.x).hypot(y - self.y) <= self.radius
+    }
+}
+
+impl Boundary for Circle {
+    fn clip_line(&self, (x, y): (f64, f64), (dx, dy): (f64, f64), (t_min, t_max): (f64, f64)) -> Option<(f64, f64)> {
+        let line = &Line { x, y, dx, dy, t_min, t_max };
+        let (t1, t2) = circle_crossings(line, self.x, self.y, self.radius)?;
+        let (t_start, t_end) = (t1.max(line.t_min), t2.min(line.t_max));
+        if t_start < t_end { Some((t_start, t_end)) } else { None }
+    }
+
+    fn interior_point(&self) -> (f64, f64) {
+        (self.x, self.y)
+    }
+
+    fn tolerance(&self) -> f64 {
+        tolerance_for(2. * self.radius, self.x.abs().max(self.y.abs()) + self.radius)
+    }
+
+    fn clamp(&self, x: f64, y: f64) -> (f64, f64) {
+        let distance = (x - self.x).hypot(y - self.y);
+        if distance <= self.radius {
+            return (x, y);
+        }
+        (self.x + (x - self.x) * self.radius / distance, self.y + (y - self.y) * self.radius / distance)
+    }
+
+    // A straight line can only touch a circle
+    fn is_along_boundary(&self, _: f64, _: f64, _: f64, _: f64) -> bool {
+        false
+    }
+
+    fn perimeter(&self) -> f64 {
+        2. * std::f64::consts::PI * self.radius
+    }
+
+    // Starts at the right of the circle
+    fn boundary_position(&self, x: f64, y: f64) -> f64 {
+        (y - self.y).atan2(x - self.x).rem_euclid(2. * std::f64::consts::PI) * self.radius
+    }
+
+    fn corners(&self) -> Vec<(f64, f64)> {
+        Vec::new()
+    }
+
+    fn arc(&self) -> Option<(f64, f64, f64)> {
+        Some((self.x, self.y, self.radius))
+    }
+}
+
+// Returns the values of t where the line crosses a circle, in increasing
+// order, or None if it misses the circle or only touches it
+pub(crate) fn circle_crossings(line: &Line, x: f64, y: f64, radius: f64) -> Option<(f64, f64)> {
+    // Solve |p + t * d - center|^2 = radius^2 for t
+    let (px, py) = (line.x - x, line.y - y);
+    let a = line.dx * line.dx + line.dy * line.dy;
+    let b = line.dx * px + line.dy * py;
+    let c = px * px + py * py - radius * radius;
+    let discriminant = b * b - a * c;
+    if discriminant <= 0. || a == 0. {
+        return None;
+    }
+    // Avoid cancellation between b and the root
+    let q = -(b + b.signum() * discriminant.sqrt());
+    let (t1, t2) = (q / a, c / q);
+    Some((t1.min(t2), t1.max(t2)))
+}
+
+// Any number of polygons, each of which can have holes and doesn't need to be
+// convex, such as land bounded by a coastline with lakes cut out of it
+pub struct PolygonRegion {
+    // Outer rings counterclockwise and holes clockwise, so that the region is
+    // always on the left
+    rings: Vec<Vec<(f64, f64)>>,
+    bounds: BoundingBox
+}
+
+impl PolygonRegion {
+    // The rings can be given in either order
+    pub fn new(mut exterior: Vec<(f64, f64)>, holes: Vec<Vec<(f64, f64)>>) -> PolygonRegion {
+        if signed_area(&exterior) < 0. {
+            exterior.reverse();
+        }
+        let mut rings = vec![exterior];
+        for mut hole in holes {
+            if signed_area(&hole) > 0. {
+                hole.reverse();
+            }
+            rings.push(hole);
+        }
+        PolygonRegion::from_rings(rings)
+    }
+
+    // Several polygons that don't overlap, such as islands
+    pub fn multi(polygons: Vec<PolygonRegion>) -> PolygonRegion {
+        PolygonRegion::from_rings(polygons.into_iter().flat_map(|polygon| polygon.rings).collect())
+    }
+
+    fn from_rings(rings: Vec<Vec<(f64, f64)>>) -> PolygonRegion {
+        let points = rings.iter().flat_map(|ring| ring.iter());
+        let (min_x, min_y, max_x, max_y) = points.fold((f64::INFINITY, f64::INFINITY, -f64::INFINITY, -f64::INFINITY), |(min_x, min_y, max_x, max_y), (x, y)| {
+            (min_x.min(*x), min_y.min(*y), max_x.max(*x), max_y.max(*y))
+        });
+        PolygonRegion { rings, bounds: BoundingBox::new(min_x, min_y, max_x, max_y) }
+    }
+
+    fn sides(&self) -> impl Iterator<Item = ((f64, f64), (f64, f64))> + '_ {
+        self.rings.iter().flat_map(|ring| {
+            ring.iter().enumerate().map(move |(i, a)| (*a, ring[(i + 1) % ring.len()]))
+        })
+    }
+
+    fn nearest_side(&self, x: f64, y: f64) -> (usize, (f64, f64)) {
+        let mut nearest = (0, (x, y));
+        let mut nearest_distance = f64::INFINITY;
+        for (i, (a, b)) in self.sides().enumerate() {
+            let point = nearest_on_segment(x, y, a, b);
+            let distance = (point.0 - x).hypot(point.1 - y);
+            if distance < nearest_distance {
+                nearest = (i, point);
+                nearest_distance = distance;
+            }
+        }
+        nearest
+    }
+}
+
+impl ClipRegion for PolygonRegion {
+    fn validate(&self) -> Result<()> {
+        if self.rings.is_empty() {
+            return Err(VoronoiError::DegenerateConfiguration("clip region must have at least one polygon"));
+        }
+        for ring in self.rings.iter() {
+            let finite = ring.iter().all(|(x, y)| x.is_finite() && y.is_finite());
+            let area = signed_area(ring);
+            if ring.len() < 3 || !finite || area == 0. || !area.is_finite() {
+                return Err(VoronoiError::DegenerateConfiguration("clip polygon rings must have at least three finite corners and an area"));
+            }
+        }
+        Ok(())
+    }
+
+    // Even-odd rule, so holes don't need to know which polygon they're in
+    fn contains(&self, x: f64, y: f64) -> bool {
+        self.rings.iter().filter(|ring| ring_contains(ring, x, y)).count() % 2 == 1
+    }
+}
+
+impl Boundary for PolygonRegion {
+    // Only clips to a box around the region, which is enough to turn rays
+    // into segments. The rings themselves are cut separately.
+    fn clip_line(&self, (x, y): (f64, f64), (dx, dy): (f64, f64), (t_min, t_max): (f64, f64)) -> Option<(f64, f64)> {
+        let line = &Line { x, y, dx, dy, t_min, t_max };
+        let margin = 0.01 * self.bounds.width().max(self.bounds.height()) + self.tolerance();
+        let (min_x, min_y) = (self.bounds.min_x - margin, self.bounds.min_y - margin);
+        let (max_x, max_y) = (self.bounds.max_x + margin, self.bounds.max_y + margin);
+        line.clip(&BoundingBox::new(min_x, min_y, max_x, max_y))
+    }
+
+    // The middle of the widest span inside the region, along a horizontal
+    // line halfway between two corners
+    fn interior_point(&self) -> (f64, f64) {
+        let mut ys: Vec<f64> = self.rings.iter().flat_map(|ring| ring.iter().map(|(_, y)| *y)).collect();
+        ys.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+        let gap = (1..ys.len()).max_by(|a, b| {
+            (ys[*a] - ys[*a - 1]).partial_cmp(&(ys[*b] - ys[*b - 1])).unwrap_or(Ordering::Equal)
+        }).unwrap_or(0);
+        let y = if gap == 0 { self.bounds.mid_y() } else { (ys[gap] + ys[gap - 1]) / 2. };
+
+        let mut xs: Vec<f64> = self.sides()
+            .filter(|((_, y1), (_, y2))| (*y1 > y) != (*y2 > y))
+            .map(|((x1, y1), (x2, y2))| x1 + (y - y1) / (y2 - y1) * (x2 - x1))
+            .collect();
+        xs.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+        let widest = (0..xs.len() / 2).max_by(|a, b| {
+            (xs[2 * a + 1] - xs[2 * a]).partial_cmp(&(xs[2 * b + 1] - xs[2 * b])).unwrap_or(Ordering::Equal)
+        });
+        match widest {
+            Some(i) => ((xs[2 * i] + xs[2 * i + 1]) / 2., y),
+            None => (self.bounds.mid_x(), y)
+        }
+    }
+
+    fn tolerance(&self) -> f64 {
+        self.bounds.tolerance()
+    }
+
+    fn clamp(&self, x: f64, y: f64) -> (f64, f64) {
+        if self.contains(x, y) {
+            return (x, y);
+        }
+        self.nearest_side(x, y).1
+    }
+
+    fn is_along_boundary(&self, x1: f64, y1: f64, x2: f64, y2: f64) -> bool {
+        let tolerance = self.tolerance();
+        self.sides().any(|(a, b)| {
+            distance_to_segment(x1, y1, a, b) <= tolerance && distance_to_segment(x2, y2, a, b) <= tolerance
+        })
+    }
+
+    fn perimeter(&self) -> f64 {
+        self.sides().map(|((x1, y1), (x2, y2))| (x2 - x1).hypot(y2 - y1)).sum()
+    }
+
+    // Measured along the rings one after the other
+    fn boundary_position(&self, x: f64, y: f64) -> f64 {
+        let (side, (nearest_x, nearest_y)) = self.nearest_side(x, y);
+        let before: f64 = self.sides().take(side).map(|((x1, y1), (x2, y2))| (x2 - x1).hypot(y2 - y1)).sum();
+        let ((corner_x, corner_y), _) = self.sides().nth(side).unwrap_or(((x, y), (x, y)));
+        before + (nearest_x - corner_x).hypot(nearest_y - corner_y)
+    }
+
+    fn corners(&self) -> Vec<(f64, f64)> {
+        self.rings.iter().flat_map(|ring| ring.iter().cloned()).collect()
+    }
+
+    fn rings(&self) -> Option<&[Vec<(f64, f64)>]> {
+        Some(&self.rings)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::clip::{ClipRegion, Boundary, BoundingBox, ConvexPolygon, PolygonRegion, Circle, Line, SideGrid, distance_to_segment};
+
+    #[test]
+    fn it_bounds_segments() {
+        fn clip(start_x: f64, start_y: f64, end_x: f64, end_y: f64, bbox: &BoundingBox) -> Option<(f64, f64, f64, f64)> {
+            let line = Line { x: start_x, y: start_y, dx: end_x - start_x, dy: end_y - start_y, t_min: 0., t_max: 1. };
+            line.clip(bbox).map(|(t_start, t_end)| {
+                (start_x + t_start * line.dx, start_y + t_start * line.dy, start_x + t_end * line.dx, start_y + t_end * line.dy)
+            })
+        }
+
+        let bbox = BoundingBox::new(-10., -10., 10., 10.);
+        assert_eq!(clip(0., 0., 100., -200., &bbox), Some((0., 0., 5., -10.)));
+        assert_eq!(clip(0., 0., 5., -2., &bbox), Some((0., 0., 5., -2.)));
+        assert_eq!(clip(100., 50., 0., 0., &bbox), Some((10., 5., 0., 0.)));
+        assert_eq!(clip(100., 50., 20., 20., &bbox), None);
+
+        // Rays and lines
+        let ray = Line { x: 0., y: 0., dx: 1., dy: 1., t_min: 0., t_max: f64::INFINITY };
+        assert_eq!(ray.clip(&bbox), Some((0., 10.)));
+        let line = Line { x: 0., y: 20., dx: 1., dy: 0., t_min: -f64::INFINITY, t_max: f64::INFINITY };
+        assert_eq!(line.clip(&bbox), None);
+        let line = Line { x: 0., y: 5., dx: -2., dy: 0., t_min: -f64::INFINITY, t_max: f64::INFINITY };
+        assert_eq!(line.clip(&bbox), Some((-5., 5.)));
+    }
+
+    #[test]
+    fn it_walks_around_the_box() {
+        let bbox = BoundingBox::new(0., 0., 4., 2.);
+        assert_eq!(bbox.corners_between(1., 0., 3., 0.), vec![]);
+        assert_eq!(bbox.corners_between(1., 0., 4., 1.), vec![(4., 0.)]);
+        assert_eq!(bbox.corners_between(0., 1., 1., 0.), vec![(0., 0.)]);
+        assert_eq!(bbox.corners_between(3., 0., 1., 0.), vec![(4., 0.), (4., 2.), (0., 2.), (0., 0.)]);
+    }
+
+    #[test]
+    fn it_clips_to_convex_polygons() {
+        // A diamond, given clockwise
+        let diamond = ConvexPolygon::new(vec![(0., 2.), (2., 0.), (0., -2.), (-2., 0.)]);
+        assert!(diamond.validate().is_ok());
+        assert!(diamond.contains(0., 0.) && diamond.contains(1., 1.) && !diamond.contains(1.5, 1.));
+
+        let line = Line { x: -4., y: 0., dx: 1., dy: 0., t_min: -f64::INFINITY, t_max: f64::INFINITY };
+        assert_eq!(line.clip(&diamond), Some((2., 6.)));
+        let ray = Line { x: 0., y: 0., dx: 0., dy: 1., t_min: 0., t_max: f64::INFINITY };
+        assert_eq!(ray.clip(&diamond), Some((0., 2.)));
+        let miss = Line { x: 3., y: 0., dx: 0., dy: 1., t_min: -f64::INFINITY, t_max: f64::INFINITY };
+        assert_eq!(miss.clip(&diamond), None);
+
+        // Corners are walked counterclockwise, starting from (0, -2)
+        assert_eq!(diamond.corners_between(1., -1., -1., 1.), vec![(2., 0.), (0., 2.)]);
+        assert_eq!(diamond.corners_between(-1., 1., 1., -1.), vec![(-2., 0.), (0., -2.)]);
+        assert!(diamond.is_along_boundary(0.5, 1.5, 1.5, 0.5));
+        assert!(!diamond.is_along_boundary(0.5, 1.5, 1.5, -0.5));
+
+        let not_convex = ConvexPolygon::new(vec![(0., 0.), (2., 0.), (1., 0.5), (2., 2.), (0., 2.)]);
+        assert!(not_convex.validate().is_err());
+        let star = ConvexPolygon::new(vec![(0., 2.), (1.2, -1.6), (-1.9, 0.6), (1.9, 0.6), (-1.2, -1.6)]);
+        assert!(star.validate().is_err());
+    }
+
+    #[test]
+    fn it_describes_polygons_with_holes() {
+        // A U shape, given clockwise, with a hole in its base
+        let exterior = vec![(0., 4.), (2., 4.), (2., 1.), (4., 1.), (4., 4.), (6., 4.), (6., 0.), (0., 0.)];
+        let hole = vec![(0.5, 0.5), (1., 0.5), (1., 1.), (0.5, 1.)];
+        let region = PolygonRegion::new(exterior, vec![hole]);
+        assert!(region.validate().is_ok());
+        assert!(region.contains(1., 3.) && region.contains(5., 3.) && region.contains(3., 0.5));
+        assert!(!region.contains(3., 3.) && !region.contains(0.75, 0.75) && !region.contains(7., 1.));
+
+        // The region is on the left of every ring
+        let rings = region.rings().unwrap();
+        assert!(rings[0].contains(&(6., 0.)) && rings[0][0] == (0., 0.) && rings[0][1] == (6., 0.));
+        assert_eq!(rings[1], vec![(0.5, 1.), (1., 1.), (1., 0.5), (0.5, 0.5)]);
+
+        let (x, y) = region.interior_point();
+        assert!(region.contains(x, y));
+        assert_eq!(region.clamp(3., 1.5), (3., 1.));
+
+        let islands = PolygonRegion::multi(vec![
+            PolygonRegion::new(vec![(0., 0.), (1., 0.), (1., 1.)], Vec::new()),
+            PolygonRegion::new(vec![(2., 0.), (3., 0.), (3., 1.)], Vec::new())
+        ]);
+        assert!(islands.contains(0.9, 0.1) && islands.contains(2.9, 0.1) && !islands.contains(1.5, 0.1));
+
+        let flat = PolygonRegion::new(vec![(0., 0.), (1., 0.), (2., 0.)], Vec::new());
+        assert!(flat.validate().is_err());
+    }
+
+    #[test]
+    fn it_buckets_ring_sides() {
+        // A wavy ring around a square hole, so that the sides are all sorts of
+        // lengths
+        let outer: Vec<(f64, f64)> = (0..200).map(|i| {
+            let angle = i as f64 / 200. * std::f64::consts::PI * 2.;
+            let radius = 4. + (angle * 9.).sin();
+            (radius * angle.cos(), radius * angle.sin())
+        }).collect();
+        let hole = vec![(-1., -1.), (-1., 1.), (1., 1.), (1., -1.)];
+        let region = PolygonRegion::new(outer, vec![hole]);
+        let rings = region.rings().unwrap();
+        let sides: Vec<((f64, f64), (f64, f64))> = rings.iter()
+            .flat_map(|ring| (0..ring.len()).map(move |i| (ring[i], ring[(i + 1) % ring.len()])))
+            .collect();
+        let tolerance = 1e-3;
+        let grid = SideGrid::new(sides.clone(), tolerance);
+
+        for i in 0..60 {
+            for j in 0..60 {
+                let (x, y) = (i as f64 / 5. - 6., j as f64 / 5. - 6.);
+                assert_eq!(grid.contains(x, y), region.contains(x, y), "{} {}", x, y);
+                let near = grid.near((x, y), (x + 0.3, y));
+                for (side, (a, b)) in sides.iter().enumerate() {
+                    if distance_to_segment(x, y, *a, *b) <= tolerance {
+                        assert!(near.contains(&side), "{} {} {}", x, y, side);
+                    }
+                }
+            }
+        }
+        // Points past the grid
+        assert!(!grid.contains(100., 0.) && !grid.contains(0., -100.) && !grid.contains(-100., 0.));
+    }
+
+    #[test]
+    fn it_clips_to_circles() {
+        let circle = Circle::new(1., 1., 2.);
+        assert!(circle.validate().is_ok());
+        assert!(circle.contains(3., 1.) && !circle.contains(2.5, 2.5));
+        assert_eq!(circle.arc(), Some((1., 1., 2.)));
+
+        let line = Line { x: -3., y: 1., dx: 1., dy: 0., t_min: -f64::INFINITY, t_max: f64::INFINITY };
+        assert_eq!(line.clip(&circle), Some((2., 6.)));
+        let ray = Line { x: 1., y: 1., dx: 0., dy: 1., t_min: 0., t_max: f64::INFINITY };
+        assert_eq!(ray.clip(&circle), Some((0., 2.)));
+        let miss = Line { x: 3.5, y: 0., dx: 0., dy: 1., t_min: -f64::INFINITY, t_max: f64::INFINITY };
+        assert_eq!(miss.clip(&circle), None);
+
+        // Positions are measured counterclockwise from the rightmost point
+        assert_eq!(circle.boundary_position(3., 1.), 0.);
+        assert!((circle.boundary_position(1., 3.) - std::f64::consts::PI).abs() < 1e-12);
+        assert_eq!(circle.clamp(5., 1.), (3., 1.));
+
+        assert!(Circle::new(0., 0., 0.).validate().is_err());
+    }
+}
@@ -1,19 +1,31 @@
 // Doubly-connected edge list for storing voronoi regions
+use crate::clip::{ClipRegion, Line, SideGrid, circle_crossings, distance_to_segment, nearest_on_segment, signed_area, ring_contains, tolerance_for};
+use crate::predicates::{orient2d, incircle};
+use crate::locate::walk_to_nearest;
+use crate::error::{Result, VoronoiError, invariant};
 use crate::math_helpers::{equals_with_epsilon};
+use crate::{Edge, UnboundedDiagram, UnboundedEdge, UnboundedCell, Cell, CellPart, CellEdge, PolygonOptions, Winding, Triangulation, SiteId, EdgeInfo, Adjacency};
+use std::collections::{HashMap, HashSet, hash_map::Entry};
 
 const NIL: usize = !0;
 
 #[derive(Debug)]
 struct Vertex {
     x: f64,
-    y: f64
+    y: f64,
+    halfedge: usize // Index of a halfedge leaving the vertex
 }
 
 #[derive(Debug)]
 struct HalfEdge {
     origin: usize, // Index of vertex point
     next: usize, // Index of next half edge
+    prev: usize, // Index of previous half edge
     twin: usize, // Index of twin half edge
+    face: usize, // Index of the face on the left, NIL outside the diagram
+    // Center and radius of the circle if the halfedge is an arc, which goes
+    // counterclockwise around it. Only set on the halfedge inside the circle.
+    arc: Option<(f64, f64, f64)>,
     active: bool
 }
 
@@ -21,36 +33,100 @@ struct HalfEdge {
 pub struct Dcel {
     vertices: Vec<Vertex>,
     halfedges: Vec<HalfEdge>,
-    faces: Vec<usize> // Array of indices of halfedges that define faces
+    faces: Vec<usize>, // Array of indices of halfedges that define faces
+    // Halfedges on the other rings of faces that were split into several
+    // parts or have holes, see `bound_to_rings`
+    other_rings: Vec<Vec<usize>>,
+    sites: Vec<(f64, f64)>, // The site that each face belongs to
+    // The face of each input site. Duplicate sites share a face.
+    input_faces: Vec<usize>,
+    // The Delaunay triangles, as the faces around each vertex of the diagram
+    // before it was clipped, counterclockwise. Triangles that `insert_site`
+    // takes out leave their slot as NILs until a new one fills it.
+    triangles: Vec<[usize; 3]>,
+    // The triangle across the side from corner i to corner i + 1 of each
+    // triangle, or NIL past the hull
+    triangle_neighbors: Vec<[usize; 3]>,
+    // A triangle at each face, or NIL if it has none
+    face_triangles: Vec<usize>,
+    free_triangles: Vec<usize>,
+    // How far the polygons can stray from arcs, relative to their radius
+    // unless it's been set
+    chord_tolerance: Option<f64>,
+    // Whether the faces have been clipped, rather than being whole cells, and
+    // whether they've been limited to the radii of their sites too
+    is_clipped: bool,
+    has_radii: bool
+}
+
+// Handles to the faces, halfedges and vertices of a diagram. Faces are numbered
+// like sites, so the face of the site with id i has index i.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FaceId(pub(crate) usize);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct HalfEdgeId(usize);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct VertexId(usize);
+
+impl FaceId {
+    pub fn index(self) -> usize {
+        self.0
+    }
+}
+
+impl HalfEdgeId {
+    pub fn index(self) -> usize {
+        self.0
+    }
+}
+
+impl VertexId {
+    pub fn index(self) -> usize {
+        self.0
+    }
 }
 
 impl HalfEdge {
-    fn new() -> HalfEdge {
-        HalfEdge { origin: NIL, next: NIL, twin: NIL, active: true }
+    fn new(face: usize) -> HalfEdge {
+        HalfEdge { origin: NIL, next: NIL, prev: NIL, twin: NIL, face, arc: None, active: true }
     }
 }
 
 impl Dcel {
-    pub fn new(face_count: usize) -> Dcel {
+    // Creates an empty diagram with one face per site
+    pub(crate) fn new(sites: Vec<(f64, f64)>) -> Dcel {
         Dcel {
             vertices: Vec::new(),
             halfedges: Vec::new(),
-            faces: vec![NIL; face_count]
+            faces: vec![NIL; sites.len()],
+            other_rings: vec![Vec::new(); sites.len()],
+            input_faces: (0..sites.len()).collect(),
+            triangles: Vec::new(),
+            triangle_neighbors: Vec::new(),
+            face_triangles: vec![NIL; sites.len()],
+            free_triangles: Vec::new(),
+            sites,
+            chord_tolerance: None,
+            is_clipped: false,
+            has_radii: false
         }
     }
 
-    pub fn ensure_face(&mut self, face_id: usize, halfedge: usize) {
+    pub(crate) fn ensure_face(&mut self, face_id: usize, halfedge: usize) {
         if self.faces[face_id] == NIL {
             self.faces[face_id] = halfedge;
         }
     }
 
-    pub fn create_twins(&mut self) -> (usize, usize) {
+    // Creates a halfedge on the given face, and its twin on the other face
+    pub(crate) fn create_twins(&mut self, face: usize, twin_face: usize) -> (usize, usize) {
         let index = self.halfedges.len();
         let twin_index = index + 1;
 
-        let mut edge = HalfEdge::new();
-        let mut twin = HalfEdge::new();
+        let mut edge = HalfEdge::new(face);
+        let mut twin = HalfEdge::new(twin_face);
 
         twin.twin = index;
         edge.twin = twin_index;
@@ -61,22 +137,106 @@ impl Dcel {
         (index, twin_index)
     }
 
-    pub fn create_vertex(&mut self, x: f64, y: f64) -> usize {
+    pub(crate) fn create_vertex(&mut self, x: f64, y: f64) -> usize {
         let index = self.vertices.len();
-        self.vertices.push(Vertex { x, y });
+        self.vertices.push(Vertex { x, y, halfedge: NIL });
         index
     }
 
-    pub fn get_twin(&self, halfedge: usize) -> usize {
+    pub(crate) fn get_twin(&self, halfedge: usize) -> usize {
         self.halfedges[halfedge].twin
     }
 
-    pub fn set_origin(&mut self, halfedge: usize, origin: usize) {
+    pub(crate) fn get_origin(&self, halfedge: usize) -> usize {
+        self.halfedges[halfedge].origin
+    }
+
+    pub(crate) fn get_vertex(&self, vertex: usize) -> (f64, f64) {
+        (self.vertices[vertex].x, self.vertices[vertex].y)
+    }
+
+    pub(crate) fn set_origin(&mut self, halfedge: usize, origin: usize) {
         self.halfedges[halfedge].origin = origin;
+        if origin == NIL {
+            return;
+        }
+        // Halfedges inside the diagram are better for walking around the vertex
+        let current = self.vertices[origin].halfedge;
+        if current == NIL || (self.halfedges[current].face == NIL && self.halfedges[halfedge].face != NIL) {
+            self.vertices[origin].halfedge = halfedge;
+        }
     }
 
-    pub fn set_next(&mut self, halfedge: usize, next: usize) {
+    // Links two halfedges, keeping the previous halfedge of both the old and
+    // the new next one up to date
+    pub(crate) fn set_next(&mut self, halfedge: usize, next: usize) {
+        let old_next = self.halfedges[halfedge].next;
+        if old_next != NIL && self.halfedges[old_next].prev == halfedge {
+            self.halfedges[old_next].prev = NIL;
+        }
         self.halfedges[halfedge].next = next;
+        if next != NIL {
+            self.halfedges[next].prev = halfedge;
+        }
+    }
+
+    // Replaces every vertex with aliases[vertex], then removes the edges that
+    // end up starting and ending at the same vertex, along with the vertices
+    // that are no longer used
+    pub(crate) fn merge_vertices(&mut self, aliases: &[usize]) {
+        for halfedge in self.halfedges.iter_mut() {
+            if halfedge.origin != NIL {
+                halfedge.origin = aliases[halfedge.origin];
+            }
+        }
+
+        for i in 0..(self.halfedges.len() / 2) {
+            let edge = i * 2;
+            let twin = i * 2 + 1;
+            let origin = self.halfedges[edge].origin;
+            if origin == NIL || origin != self.halfedges[twin].origin {
+                continue;
+            }
+
+            // Splice both halfedges out of their faces
+            for halfedge in [edge, twin].iter() {
+                let halfedge = *halfedge;
+                let before = self.halfedges[halfedge].prev;
+                let after = self.halfedges[halfedge].next;
+                self.set_next(halfedge, NIL);
+                if before != NIL {
+                    self.set_next(before, after);
+                } else if after != NIL {
+                    self.halfedges[after].prev = NIL;
+                }
+                let face = self.halfedges[halfedge].face;
+                if face != NIL && self.faces[face] == halfedge {
+                    self.faces[face] = if after != NIL { after } else { before };
+                }
+                self.halfedges[halfedge].active = false;
+            }
+        }
+
+        // Compact the vertices that are still referenced
+        let mut new_indices = vec![NIL; self.vertices.len()];
+        let mut vertices = Vec::new();
+        for (i, vertex) in self.vertices.iter().enumerate() {
+            if aliases[i] == i {
+                new_indices[i] = vertices.len();
+                vertices.push(Vertex { x: vertex.x, y: vertex.y, halfedge: NIL });
+            }
+        }
+        for halfedge in self.halfedges.iter_mut() {
+            if halfedge.origin != NIL {
+                halfedge.origin = new_indices[halfedge.origin];
+            }
+        }
+        self.vertices = vertices;
+        for i in 0..self.halfedges.len() {
+            if self.halfedges[i].active && self.halfedges[i].origin != NIL {
+                self.set_origin(i, self.halfedges[i].origin);
+            }
+        }
     }
 
     pub fn get_edges(&self) -> Vec<(f64, f64, f64, f64)> {
@@ -93,430 +253,1979 @@ impl Dcel {
         edges
     }
 
+    // Faces of sites on the convex hull aren't closed before clipping, so
+    // they're made to start at the halfedge coming in from infinity. If all
+    // sites are collinear, a face can be a strip between two lines, and the
+    // second one goes with the other rings.
+    pub(crate) fn find_open_chains(&mut self) {
+        let mut chains = vec![Vec::new(); self.faces.len()];
+        for (i, halfedge) in self.halfedges.iter().enumerate() {
+            if halfedge.active && halfedge.face != NIL && halfedge.prev == NIL {
+                chains[halfedge.face].push(i);
+            }
+        }
+        for (face, chains) in chains.into_iter().enumerate() {
+            if let Some((first, others)) = chains.split_first() {
+                self.faces[face] = *first;
+                self.other_rings[face] = others.to_vec();
+            }
+        }
+    }
+
+    // Joins the faces around every vertex into triangles, fanning out from the
+    // first one where there are more than three. Only works before clipping.
+    pub(crate) fn find_triangles(&mut self) {
+        let mut triangles = Vec::new();
+        for vertex in self.vertices() {
+            let faces: Vec<usize> = self.vertex_faces(vertex).map(|face| face.0).collect();
+            for i in 2..faces.len() {
+                triangles.push([faces[0], faces[i - 1], faces[i]]);
+            }
+        }
+
+        let mut triangles_by_side = HashMap::new();
+        for (triangle, faces) in triangles.iter().enumerate() {
+            for i in 0..3 {
+                triangles_by_side.insert((faces[i], faces[(i + 1) % 3]), triangle);
+            }
+        }
+        self.triangle_neighbors = triangles.iter().map(|faces| {
+            let mut neighbors = [NIL; 3];
+            for (i, neighbor) in neighbors.iter_mut().enumerate() {
+                *neighbor = triangles_by_side.get(&(faces[(i + 1) % 3], faces[i])).copied().unwrap_or(NIL);
+            }
+            neighbors
+        }).collect();
+        self.face_triangles = vec![NIL; self.sites.len()];
+        for (triangle, faces) in triangles.iter().enumerate() {
+            for face in faces {
+                self.face_triangles[*face] = triangle;
+            }
+        }
+        self.triangles = triangles;
+        self.free_triangles.clear();
+    }
+
+    // Returns the Delaunay triangulation of the sites, which doesn't depend on
+    // how the diagram was clipped
+    pub fn get_triangulation(&self) -> Triangulation {
+        // Leave out the empty slots, numbering the triangles that are left in
+        // order
+        let mut numbers = vec![NIL; self.triangles.len()];
+        let mut triangles = Vec::with_capacity(self.triangles.len() - self.free_triangles.len());
+        for (triangle, faces) in self.triangles.iter().enumerate() {
+            if faces[0] != NIL {
+                numbers[triangle] = triangles.len();
+                triangles.push(*faces);
+            }
+        }
+        let neighbors = self.triangle_neighbors.iter().enumerate()
+            .filter(|(triangle, _)| numbers[*triangle] != NIL)
+            .map(|(_, neighbors)| {
+                let mut numbered = [None; 3];
+                for (numbered, neighbor) in numbered.iter_mut().zip(neighbors) {
+                    if *neighbor != NIL {
+                        *numbered = Some(numbers[*neighbor]);
+                    }
+                }
+                numbered
+            })
+            .collect();
+        Triangulation { triangles, neighbors }
+    }
+
+    pub(crate) fn set_input_faces(&mut self, input_faces: Vec<usize>) {
+        self.input_faces = input_faces;
+    }
+
+    // Polygons approximate arcs with chords, so that no point of an arc is
+    // farther than this from them
+    pub fn set_chord_tolerance(&mut self, tolerance: f64) {
+        self.chord_tolerance = Some(tolerance);
+    }
+
+    // Lists the polygon of every face, counterclockwise. Faces that aren't
+    // closed (or don't exist) get an empty polygon.
     pub fn get_polygons(&self) -> Vec<Vec<(f64, f64)>> {
-        let mut polygons = Vec::with_capacity(self.faces.len());
-        let face_count = self.faces.len();
-        for i in 0..face_count {
-            let face = self.faces[i];
-            let mut edge = face;
-            let mut polygon = Vec::new();
-            let mut last_x = std::f64::MAX;
-            let mut last_y = std::f64::MAX;
+        self.faces.iter().map(|face| self.get_ring(*face).unwrap_or_default()).collect()
+    }
+
+    // Like `get_polygons`, but written out as the options say
+    pub fn get_polygons_with(&self, options: &PolygonOptions) -> Vec<Vec<(f64, f64)>> {
+        self.get_polygons().into_iter().map(|polygon| format_ring(polygon, options)).collect()
+    }
+
+    // Like `get_multipolygons`, but written out as the options say. Parts and
+    // holes with nothing left after removing collinear points are dropped.
+    pub fn get_multipolygons_with(&self, options: &PolygonOptions) -> Vec<Vec<CellPart>> {
+        self.get_multipolygons().into_iter().map(|parts| {
+            parts.into_iter().filter_map(|part| {
+                let exterior = format_ring(part.exterior, options);
+                let holes = part.holes.into_iter()
+                    .map(|hole| format_ring(hole, options))
+                    .filter(|hole| !hole.is_empty())
+                    .collect();
+                if exterior.is_empty() { None } else { Some(CellPart { exterior, holes }) }
+            }).collect()
+        }).collect()
+    }
+
+    // Lists the cell of every input site along with its index in the input.
+    // Before clipping, cells that don't close up are unbounded rather than
+    // outside.
+    pub fn cells(&self) -> impl Iterator<Item = (usize, Cell)> + '_ {
+        self.input_faces.iter().enumerate().map(move |(input, face)| {
+            let mut parts = self.get_cell_parts(*face);
+            let cell = match parts.len() {
+                0 if !self.is_clipped => Cell::Unbounded,
+                0 => Cell::Outside,
+                1 if parts[0].holes.is_empty() => Cell::Polygon(parts.remove(0).exterior),
+                _ => Cell::Parts(parts)
+            };
+            (input, cell)
+        })
+    }
+
+    // Lists the parts of every face. Clipping to a region that isn't convex
+    // can split a cell into several parts, and cut holes into them.
+    pub fn get_multipolygons(&self) -> Vec<Vec<CellPart>> {
+        (0..self.faces.len()).map(|face| self.get_cell_parts(face)).collect()
+    }
+
+    fn get_cell_parts(&self, face: usize) -> Vec<CellPart> {
+        let rings = std::iter::once(&self.faces[face]).chain(self.other_rings[face].iter())
+            .filter_map(|halfedge| self.get_ring(*halfedge))
+            .filter(|ring| ring.len() >= 3);
+        let (exteriors, holes): (Vec<_>, Vec<_>) = rings.partition(|ring| signed_area(ring) > 0.);
+        let mut parts: Vec<CellPart> = exteriors.into_iter().map(|exterior| CellPart { exterior, holes: Vec::new() }).collect();
+
+        // Each hole goes into the smallest part around it
+        for hole in holes {
+            let ((x1, y1), (x2, y2)) = (hole[0], hole[1]);
+            let (x, y) = ((x1 + x2) / 2., (y1 + y2) / 2.);
+            let around = (0..parts.len())
+                .filter(|part| ring_contains(&parts[*part].exterior, x, y))
+                .min_by(|a, b| {
+                    signed_area(&parts[*a].exterior).partial_cmp(&signed_area(&parts[*b].exterior)).unwrap_or(std::cmp::Ordering::Equal)
+                });
+            if let Some(part) = around {
+                parts[part].holes.push(hole);
+            }
+        }
+        parts
+    }
+
+    // Walks the ring of halfedges starting at the given one, returning None if
+    // it isn't closed
+    fn get_ring(&self, start: usize) -> Option<Vec<(f64, f64)>> {
+        let mut edge = start;
+        let mut ring = Vec::new();
+        let mut last_x = f64::MAX;
+        let mut last_y = f64::MAX;
+        loop {
+            if edge == NIL || self.halfedges[edge].origin == NIL {
+                return None;
+            }
+            let vertex = &self.vertices[self.halfedges[edge].origin];
+            if !equals_with_epsilon(vertex.x, last_x) || !equals_with_epsilon(vertex.y, last_y) {
+                ring.push((vertex.x, vertex.y));
+            }
+            if let Some(arc) = self.halfedges[edge].arc {
+                ring.extend(self.get_chords(edge, arc));
+            }
+            edge = self.halfedges[edge].next;
+            last_x = vertex.x;
+            last_y = vertex.y;
+
+            if edge == start {
+                // We made it back to the first point, so we have a full ring
+                return Some(ring);
+            }
+        }
+    }
+
+    // Returns the points between the ends of an arc where it's split into
+    // chords
+    pub(crate) fn get_chords(&self, halfedge: usize, (x, y, radius): (f64, f64, f64)) -> Vec<(f64, f64)> {
+        let (start, sweep) = self.get_arc_angles(halfedge, (x, y));
+
+        // A chord spanning an angle a is radius * (1 - cos(a / 2)) from the arc
+        let tolerance = self.chord_tolerance.unwrap_or(1e-3 * radius).min(radius);
+        let max_angle = 2. * (1. - tolerance / radius).acos();
+        let count = if max_angle > 0. { (sweep / max_angle).ceil().min(1e6) as usize } else { 1 };
+        (1..count).map(|i| {
+            let angle = start + sweep * i as f64 / count as f64;
+            (x + radius * angle.cos(), y + radius * angle.sin())
+        }).collect()
+    }
+
+    // Returns the angle that an arc starts at around its center, and how far
+    // counterclockwise it goes from there. An arc that ends where it started
+    // goes all the way around.
+    fn get_arc_angles(&self, halfedge: usize, (x, y): (f64, f64)) -> (f64, f64) {
+        let (x1, y1) = self.get_vertex(self.halfedges[halfedge].origin);
+        let (x2, y2) = self.get_vertex(self.get_origin(self.get_twin(halfedge)));
+        let start = (y1 - y).atan2(x1 - x);
+        let sweep = ((y2 - y).atan2(x2 - x) - start).rem_euclid(2. * std::f64::consts::PI);
+        (start, if sweep == 0. { 2. * std::f64::consts::PI } else { sweep })
+    }
+
+    // Lists the edges around every face, with arcs as they are rather than
+    // split into chords. Like `get_polygons`, this only covers the first part
+    // of faces that were split.
+    pub fn get_cell_edges(&self) -> Vec<Vec<CellEdge>> {
+        self.faces.iter().map(|first| {
+            let mut edges = Vec::new();
+            let mut halfedge = *first;
             loop {
-                if edge == NIL || self.halfedges[edge].origin == NIL {
-                    break;
+                if halfedge == NIL || self.halfedges[halfedge].origin == NIL {
+                    return Vec::new();
                 }
-                let vertex = &self.vertices[self.halfedges[edge].origin];
-                if !equals_with_epsilon(vertex.x, last_x) || !equals_with_epsilon(vertex.y, last_y) {
-                    polygon.push((vertex.x, vertex.y));
+                let from = self.get_vertex(self.halfedges[halfedge].origin);
+                let to = self.get_vertex(self.get_origin(self.get_twin(halfedge)));
+                edges.push(match self.halfedges[halfedge].arc {
+                    Some((x, y, radius)) => CellEdge::Arc { from, to, center: (x, y), radius },
+                    None => CellEdge::Segment { from, to }
+                });
+                halfedge = self.halfedges[halfedge].next;
+                if halfedge == *first {
+                    return edges;
                 }
-                edge = self.halfedges[edge].next;
-                last_x = vertex.x;
-                last_y = vertex.y;
+            }
+        }).collect()
+    }
 
-                // println!("Building {} {} ", edge, face);
-                if edge == face {
-                    break;
-                }
+    pub fn faces(&self) -> impl Iterator<Item = FaceId> {
+        (0..self.faces.len()).map(FaceId)
+    }
+
+    pub fn halfedges(&self) -> impl Iterator<Item = HalfEdgeId> + '_ {
+        (0..self.halfedges.len())
+            .filter(move |halfedge| self.halfedges[*halfedge].active && self.halfedges[*halfedge].origin != NIL)
+            .map(HalfEdgeId)
+    }
+
+    // Lists the vertices, leaving out ones that `insert_site` took every
+    // halfedge away from
+    pub fn vertices(&self) -> impl Iterator<Item = VertexId> + '_ {
+        (0..self.vertices.len()).filter(move |vertex| self.vertices[*vertex].halfedge != NIL).map(VertexId)
+    }
+
+    // The site that a face belongs to
+    pub fn site(&self, face: FaceId) -> (f64, f64) {
+        self.sites[face.0]
+    }
+
+    pub fn position(&self, vertex: VertexId) -> (f64, f64) {
+        self.get_vertex(vertex.0)
+    }
+
+    // The vertex a halfedge starts at, None if it comes in from infinity,
+    // which only the rays of a diagram that hasn't been clipped do
+    pub fn origin(&self, halfedge: HalfEdgeId) -> Option<VertexId> {
+        let origin = self.halfedges[halfedge.0].origin;
+        if origin == NIL { None } else { Some(VertexId(origin)) }
+    }
+
+    pub fn twin(&self, halfedge: HalfEdgeId) -> HalfEdgeId {
+        HalfEdgeId(self.halfedges[halfedge.0].twin)
+    }
+
+    // The face on the left of a halfedge, None if it's outside the diagram
+    pub fn face(&self, halfedge: HalfEdgeId) -> Option<FaceId> {
+        let face = self.halfedges[halfedge.0].face;
+        if face == NIL { None } else { Some(FaceId(face)) }
+    }
+
+    // The next halfedge around the face. Halfedges outside the diagram are
+    // linked around the outside of it.
+    pub fn next(&self, halfedge: HalfEdgeId) -> Option<HalfEdgeId> {
+        let next = self.halfedges[halfedge.0].next;
+        if next == NIL { None } else { Some(HalfEdgeId(next)) }
+    }
+
+    pub fn prev(&self, halfedge: HalfEdgeId) -> Option<HalfEdgeId> {
+        let prev = self.halfedges[halfedge.0].prev;
+        if prev == NIL { None } else { Some(HalfEdgeId(prev)) }
+    }
+
+    // The center and radius of the circle if the halfedge is an arc
+    pub fn arc(&self, halfedge: HalfEdgeId) -> Option<(f64, f64, f64)> {
+        self.halfedges[halfedge.0].arc
+    }
+
+    // Lists the halfedges around a face, counterclockwise and one ring after
+    // the other if it has several, see `get_multipolygons`
+    pub fn face_halfedges(&self, face: FaceId) -> impl Iterator<Item = HalfEdgeId> + '_ {
+        let starts = std::iter::once(self.faces[face.0]).chain(self.other_rings[face.0].iter().copied());
+        starts.filter(|start| *start != NIL).flat_map(move |start| {
+            std::iter::successors(Some(start), move |halfedge| {
+                let next = self.halfedges[*halfedge].next;
+                if next == NIL || next == start { None } else { Some(next) }
+            }).map(HalfEdgeId)
+        })
+    }
+
+    // Lists the sites that share an edge with a site, in order around it. A
+    // site that shares several edges with it (after clipping to a region that
+    // isn't convex) comes up once, with their total length.
+    pub fn neighbors(&self, site: SiteId) -> impl Iterator<Item = (SiteId, EdgeInfo)> {
+        let mut neighbors: Vec<(SiteId, EdgeInfo)> = Vec::new();
+        for halfedge in self.face_halfedges(FaceId(site)) {
+            let neighbor = self.halfedges[self.get_twin(halfedge.0)].face;
+            if neighbor == NIL {
+                continue;
+            }
+            let (start, end) = (self.halfedges[halfedge.0].origin, self.get_origin(self.get_twin(halfedge.0)));
+            let length = if start == NIL || end == NIL {
+                f64::INFINITY
+            } else {
+                let (x1, y1) = self.get_vertex(start);
+                let (x2, y2) = self.get_vertex(end);
+                (x2 - x1).hypot(y2 - y1)
+            };
+            match neighbors.iter_mut().find(|(other, _)| *other == neighbor) {
+                Some((_, info)) => info.length += length,
+                None => neighbors.push((neighbor, EdgeInfo { length, halfedge }))
             }
+        }
+        neighbors.into_iter()
+    }
 
-            if edge == face {
-                // We made it back to the first point, so we have a full polygon
-                polygons.push(polygon);
+    // Lists the neighbors of every site at once, see `neighbors`
+    pub fn get_adjacency(&self) -> Adjacency {
+        let mut adjacency = Adjacency { offsets: vec![0], neighbors: Vec::new(), lengths: Vec::new() };
+        for site in 0..self.faces.len() {
+            for (neighbor, info) in self.neighbors(site) {
+                adjacency.neighbors.push(neighbor);
+                adjacency.lengths.push(info.length);
+            }
+            adjacency.offsets.push(adjacency.neighbors.len());
+        }
+        adjacency
+    }
+
+    // Lists the halfedges leaving a vertex, counterclockwise
+    pub fn vertex_halfedges(&self, vertex: VertexId) -> impl Iterator<Item = HalfEdgeId> {
+        let start = self.vertices[vertex.0].halfedge;
+        let mut halfedges = Vec::new();
+        let mut halfedge = start;
+        // The halfedge after the twin of one is the next one clockwise
+        while halfedge != NIL {
+            halfedges.push(HalfEdgeId(halfedge));
+            halfedge = self.halfedges[self.get_twin(halfedge)].next;
+            if halfedge == start {
+                break;
+            }
+        }
+        halfedges.reverse();
+        halfedges.into_iter()
+    }
+
+    // Lists the faces around a vertex, counterclockwise. A face that touches
+    // itself at the vertex comes up once for each corner it has there.
+    pub fn vertex_faces(&self, vertex: VertexId) -> impl Iterator<Item = FaceId> + '_ {
+        self.vertex_halfedges(vertex).filter_map(move |halfedge| self.face(halfedge))
+    }
+
+    // Lists the vertices and edges of a diagram that hasn't been clipped yet
+    pub fn get_unbounded(&self) -> UnboundedDiagram {
+        let vertices = self.vertices.iter().map(|vertex| (vertex.x, vertex.y)).collect();
+        let mut edges = Vec::new();
+        for i in 0..(self.halfedges.len() / 2) {
+            let edge = i * 2;
+            let twin = i * 2 + 1;
+            if !self.halfedges[edge].active {
+                continue;
+            }
+            let origin = self.halfedges[edge].origin;
+            let end = self.halfedges[twin].origin;
+            let left = self.halfedges[edge].face;
+            let right = self.halfedges[twin].face;
+            let line = self.get_line(edge);
+            let (edge, sites) = match (origin != NIL, end != NIL) {
+                (true, true) => {
+                    let (from, to) = (&self.vertices[origin], &self.vertices[end]);
+                    (Edge::Full(from.x, from.y, to.x, to.y), (left, right))
+                },
+                (true, false) => {
+                    let from = &self.vertices[origin];
+                    (Edge::Half(from.x, from.y, line.dx, line.dy), (left, right))
+                },
+                // Rays always start at their vertex, so this one is reversed
+                (false, true) => {
+                    let to = &self.vertices[end];
+                    (Edge::Half(to.x, to.y, -line.dx, -line.dy), (right, left))
+                },
+                (false, false) => (Edge::Line(line.x, line.y, line.dx, line.dy), (left, right))
+            };
+            edges.push(UnboundedEdge { edge, sites });
+        }
+
+        // Open chains are listed from the halfedge coming in from infinity
+        let cells = self.get_face_halfedges().into_iter().map(|halfedges| {
+            let is_line = |halfedge: &usize| self.halfedges[*halfedge].origin == NIL && self.get_origin(self.get_twin(*halfedge)) == NIL;
+            if halfedges.iter().all(is_line) {
+                let lines = halfedges.iter().map(|halfedge| {
+                    let line = self.get_line(*halfedge);
+                    (line.x, line.y, line.dx, line.dy)
+                }).collect();
+                return UnboundedCell::Lines(lines);
+            }
+            let ends: Vec<(f64, f64)> = halfedges.iter()
+                .map(|halfedge| self.get_origin(self.get_twin(*halfedge)))
+                .filter(|vertex| *vertex != NIL)
+                .map(|vertex| self.get_vertex(vertex))
+                .collect();
+            let first = halfedges[0];
+            let last = halfedges[halfedges.len() - 1];
+            if self.halfedges[first].origin == NIL {
+                let incoming = self.get_line(first);
+                let outgoing = self.get_line(last);
+                UnboundedCell::Open { incoming: (incoming.dx, incoming.dy), vertices: ends, outgoing: (outgoing.dx, outgoing.dy) }
             } else {
-                // We only have a partial polygon, so just push an empty polygon
-                polygons.push(Vec::new());
-            }
-        }
-        polygons
-    }
-
-    pub fn bound(&mut self, bbox: &BoundingBox) {
-        let mut faces_to_remove = Vec::new();
-        let face_count = self.faces.len();
-        for i in 0..face_count {
-            let face = self.faces[i];
-            // Find a edge whose origin is inside bbox -- if one doesn't exist, mark this face for removal
-            // Set STATE to INSIDE
-            // For each edge.next until we're back:
-            //   If STATE is INSIDE:
-            //     If segment intersects EXITING_SIDE of bounding box:
-            //       Change STATE to OUTSIDE
-            //       Add new vertex at intersect's x,y and name it OUT_VERTEX
-            //       Store EXITING_SIDE
-            //       Store current halfedge as EXITING_EDGE
-            //   If STATE is OUTSIDE:
-            //     Mark current halfedge as inactive
-            //     If segment intersects with ENTERING_SIDE of bounding box:
-            //       If ENTERING_SIDE is not equal to EXITING_SIDE:
-            //         For each corner between EXITING_SIDE and ENTERING_SIDE:
-            //           Add new vertex at corner and name it CORNER
-            //           Add a new halfedge CORNER_EDGE with origin OUT_VERTEX
-            //           Set EXITING_EDGE.next to CORNER_EDGE
-            //           Set OUT_VERTEX to CORNER
-            //           Set EXITING_EDGE to CORNER_EDGE
-            //       Add new vertex at intersect's x,y and name it IN_VERTEX
-            //       Add a new halfedge BBOX_EDGE with origin OUT_VERTEX
-            //       Add a new halfedge ENTERING_EDGE with origin IN_VERTEX
-            //       Set EXITING_EDGE.next to BBOX_EDGE
-            //       Set BBOX_EDGE.next to ENTERING_EDGE
-            //       Set ENTERING_EDGE.next to current edge's next
-            //       Break out of the loop
-            //
-            let mut starting_edge = face;
-            let mut should_remove_face = false;
+                // Every vertex ends one halfedge, so the ring starts at the end
+                // of the last one
+                let mut vertices = ends;
+                vertices.rotate_right(1);
+                UnboundedCell::Closed(vertices)
+            }
+        }).collect();
+        UnboundedDiagram { vertices, edges, cells }
+    }
 
-            loop {
-                if self.halfedges[starting_edge].origin == NIL {
-                    // Only bound faces that are complete
-                    should_remove_face = true;
-                    break;
+    // Returns the diagram clipped to a region, leaving this one as it is, so
+    // that the same diagram can be clipped to any number of regions. Only
+    // diagrams that haven't been clipped yet can be clipped, see
+    // `Voronoi::run_unclipped`.
+    pub fn clip(&self, region: &dyn ClipRegion) -> Result<Dcel> {
+        if self.is_clipped {
+            return Err(VoronoiError::DegenerateConfiguration("only a diagram that hasn't been clipped yet can be clipped to a region"));
+        }
+        region.validate()?;
+        Ok(self.bound(region))
+    }
+
+    // Adds a site to a clipped diagram and returns its id, which is the next
+    // one after the others. Only the cells around the site change: each one
+    // loses the part of it that's nearer to the new site, and those parts make
+    // up the new cell. That's the same as clipping the diagram of all the
+    // sites to the region again, with the new one added at the end. Diagrams
+    // limited to radii can't take new sites, since there's no radius for them.
+    pub fn insert_site(&mut self, x: f64, y: f64) -> Result<SiteId> {
+        let input = self.input_faces.len();
+        if !x.is_finite() || !y.is_finite() {
+            return Err(VoronoiError::NonFiniteCoordinate { site: input });
+        }
+        let nearest = self.nearest_face((x, y));
+        if let Some(face) = nearest.filter(|face| {
+            let (site_x, site_y) = self.sites[*face];
+            equals_with_epsilon(site_x, x) && equals_with_epsilon(site_y, y)
+        }) {
+            let first = self.input_faces.iter().position(|other| *other == face).unwrap_or(face);
+            return Err(VoronoiError::DuplicateSites { first, second: input });
+        }
+        if !self.is_clipped || self.has_radii {
+            return Err(VoronoiError::DegenerateConfiguration("sites can only be inserted into a diagram clipped to a region"));
+        }
+
+        let site = self.sites.len();
+        self.sites.push((x, y));
+        self.faces.push(NIL);
+        self.other_rings.push(Vec::new());
+        self.input_faces.push(site);
+        self.face_triangles.push(NIL);
+        let neighbors = self.insert_triangles(site, nearest);
+        self.carve_face(site, &neighbors)?;
+        Ok(site)
+    }
+
+    // Whether the diagram has been clipped. Faces of an unclipped diagram go on
+    // to infinity unless they're closed in by other sites.
+    pub fn is_clipped(&self) -> bool {
+        self.is_clipped
+    }
+
+    // Creates a diagram with the same sites and settings, but no edges yet
+    fn empty_copy(&self) -> Dcel {
+        let mut dcel = Dcel::new(self.sites.clone());
+        dcel.input_faces = self.input_faces.clone();
+        dcel.triangles = self.triangles.clone();
+        dcel.triangle_neighbors = self.triangle_neighbors.clone();
+        dcel.face_triangles = self.face_triangles.clone();
+        dcel.free_triangles = self.free_triangles.clone();
+        dcel.chord_tolerance = self.chord_tolerance;
+        dcel.is_clipped = true;
+        dcel.has_radii = self.has_radii;
+        dcel
+    }
+
+    // Clips the diagram to a region. Edges that cross the region are cut where
+    // they cross its boundary, edges outside of it are dropped, and the gaps
+    // that leaves in each face are closed by walking along the boundary. Faces
+    // of sites whose cell doesn't overlap the region end up empty.
+    fn bound(&self, region: &dyn ClipRegion) -> Dcel {
+        if let Some(rings) = region.rings() {
+            return self.bound_to_rings(region, rings);
+        }
+
+        let mut clipped = self.empty_copy();
+
+        // Clip every edge once, so that both of its halfedges share the new
+        // vertices. Original vertices are only copied over if they're used.
+        let tolerance = region.tolerance();
+        let mut new_vertices = vec![NIL; self.vertices.len()];
+        let mut new_halfedges = vec![NIL; self.halfedges.len()];
+        let mut vertices_by_position = HashMap::new();
+        for i in 0..(self.halfedges.len() / 2) {
+            let edge = i * 2;
+            let twin = i * 2 + 1;
+            if !self.halfedges[edge].active {
+                continue;
+            }
+            let origin = self.halfedges[edge].origin;
+            let end = self.halfedges[twin].origin;
+            let line = self.get_line(edge);
+            let (t_start, t_end) = if self.is_vertex_inside(origin, region) && self.is_vertex_inside(end, region) {
+                // Nothing to clip
+                (line.t_min, line.t_max)
+            } else {
+                match line.clip(region) {
+                    Some(range) => range,
+                    None => continue
                 }
-                let vertex = &self.vertices[self.halfedges[starting_edge].origin];
-                if is_inside(vertex.x, vertex.y, bbox) {
-                    // This edge is inside, we can use it as a starting edge
-                    break;
+            };
+
+            // Returns the vertex at t, preferring the original vertex at that end
+            // of the edge, then the one at the other end
+            let mut vertex_at = |dcel: &mut Dcel, t: f64, originals: [usize; 2]| {
+                // Rounding can put the point slightly outside the region
+                let (x, y) = region.clamp(line.x + t * line.dx, line.y + t * line.dy);
+                for (i, original) in originals.iter().enumerate() {
+                    if *original == NIL {
+                        continue;
+                    }
+                    // A vertex on the border can end up on either side of it,
+                    // but all of its edges should still meet there
+                    let vertex = &self.vertices[*original];
+                    let is_original = i == 0 && (t == line.t_min || t == line.t_max);
+                    if is_original || ((vertex.x - x).abs() <= tolerance && (vertex.y - y).abs() <= tolerance) {
+                        if new_vertices[*original] == NIL {
+                            let (x, y) = region.clamp(vertex.x, vertex.y);
+                            new_vertices[*original] = dcel.create_vertex(x, y);
+                        }
+                        return new_vertices[*original];
+                    }
+                }
+                // Edges can also cross the border at the same point, e.g. at a
+                // corner
+                *vertices_by_position.entry((x.to_bits(), y.to_bits())).or_insert_with(|| dcel.create_vertex(x, y))
+            };
+            let start = vertex_at(&mut clipped, t_start, [origin, end]);
+            let end = vertex_at(&mut clipped, t_end, [end, origin]);
+            if start == end {
+                // Only touches the region, or is too short to matter
+                continue;
+            }
+
+            // An edge along the border only bounds the face on the inside
+            let (start_x, start_y) = clipped.get_vertex(start);
+            let (end_x, end_y) = clipped.get_vertex(end);
+            let mut face = self.halfedges[edge].face;
+            let mut twin_face = self.halfedges[twin].face;
+            if region.is_along_boundary(start_x, start_y, end_x, end_y) {
+                let (inside_x, inside_y) = region.interior_point();
+                let turn = (end_x - start_x) * (inside_y - start_y) - (end_y - start_y) * (inside_x - start_x);
+                if turn > 0. { twin_face = NIL; } else { face = NIL; }
+            }
+
+            let (new_edge, new_twin) = clipped.create_twins(face, twin_face);
+            clipped.set_origin(new_edge, start);
+            clipped.set_origin(new_twin, end);
+            new_halfedges[edge] = new_edge;
+            new_halfedges[twin] = new_twin;
+        }
+
+        // The region lies entirely inside the cell of the site nearest to any
+        // point in it if no edge crosses it
+        let (mid_x, mid_y) = region.interior_point();
+        let mut nearest_face = NIL;
+        let mut nearest_distance = f64::INFINITY;
+        for (face, (x, y)) in self.sites.iter().enumerate() {
+            let distance = (x - mid_x) * (x - mid_x) + (y - mid_y) * (y - mid_y);
+            if distance < nearest_distance {
+                nearest_face = face;
+                nearest_distance = distance;
+            }
+        }
+
+        let faces = self.get_face_halfedges();
+        for (face, halfedges) in faces.iter().enumerate() {
+            let kept: Vec<usize> = halfedges.iter()
+                .map(|halfedge| new_halfedges[*halfedge])
+                .filter(|halfedge| *halfedge != NIL && clipped.halfedges[*halfedge].face != NIL)
+                .collect();
+
+            if kept.is_empty() {
+                if face == nearest_face {
+                    // A circle needs two vertices for two arcs around it
+                    let corners = match region.arc() {
+                        Some((x, y, radius)) => vec![(x + radius, y), (x - radius, y)],
+                        None => region.corners()
+                    };
+                    let vertices: Vec<usize> = corners.iter().map(|(x, y)| clipped.create_vertex(*x, *y)).collect();
+                    let first = clipped.create_boundary_path(face, &vertices, vertices[0], region.arc());
+                    clipped.faces[face] = first;
                 }
-                starting_edge = self.halfedges[starting_edge].next;
-                if starting_edge == face || starting_edge == NIL {
-                    // We're back to the beginning and we didn't find a vertex inside
-                    should_remove_face = true;
-                    break;
+                continue;
+            }
+
+            // Consecutive edges either meet at a vertex, or they leave and
+            // re-enter the region, in which case the face continues along its
+            // boundary
+            for (i, halfedge) in kept.iter().enumerate() {
+                let next = kept[(i + 1) % kept.len()];
+                let end = clipped.get_origin(clipped.get_twin(*halfedge));
+                let start = clipped.get_origin(next);
+                if end == start {
+                    clipped.set_next(*halfedge, next);
+                    continue;
+                }
+                let (end_x, end_y) = clipped.get_vertex(end);
+                let (start_x, start_y) = clipped.get_vertex(start);
+                let mut path = vec![end];
+                for (x, y) in region.corners_between(end_x, end_y, start_x, start_y) {
+                    path.push(clipped.create_vertex(x, y));
+                }
+                let first = clipped.create_boundary_path(face, &path, start, region.arc());
+                clipped.set_next(*halfedge, first);
+                let last = clipped.halfedges.len() - 2;
+                clipped.set_next(last, next);
+            }
+            clipped.faces[face] = kept[0];
+        }
+
+        clipped.link_halfedges(true);
+        clipped
+    }
+
+    // Clips the diagram to a region that isn't convex. Every edge is cut where
+    // it crosses a ring and its parts inside the region are kept. Then every
+    // side of a ring is cut where edges cross it, and each part of it goes to
+    // the face of the cell that it's in. Faces are closed by linking their
+    // halfedges at shared vertices, and can end up with several rings.
+    fn bound_to_rings(&self, region: &dyn ClipRegion, rings: &[Vec<(f64, f64)>]) -> Dcel {
+        let mut clipped = self.empty_copy();
+        if self.sites.is_empty() {
+            return clipped;
+        }
+        let tolerance = region.tolerance();
+
+        let ring_vertices: Vec<Vec<usize>> = rings.iter().map(|ring| {
+            ring.iter().map(|(x, y)| clipped.create_vertex(*x, *y)).collect()
+        }).collect();
+        // Every side of every ring, as its ring and the index of its first corner
+        let sides: Vec<(usize, usize)> = rings.iter().enumerate()
+            .flat_map(|(ring, corners)| (0..corners.len()).map(move |corner| (ring, corner)))
+            .collect();
+        let side_ends = |(ring, corner): (usize, usize)| {
+            (rings[ring][corner], rings[ring][(corner + 1) % rings[ring].len()])
+        };
+        // Only the sides near an edge or a point are looked at
+        let grid = SideGrid::new(sides.iter().map(|side| side_ends(*side)).collect(), tolerance);
+        let is_on_boundary = |x: f64, y: f64| {
+            grid.near((x, y), (x, y)).into_iter().any(|side| {
+                let (a, b) = grid.side(side);
+                distance_to_segment(x, y, a, b) <= tolerance
+            })
+        };
+
+        // Where edges cross each side, as the fraction of the way along it and
+        // the vertex there, and the faces of the edges through each vertex
+        let mut side_splits: Vec<Vec<(f64, usize)>> = vec![Vec::new(); sides.len()];
+        let mut vertex_faces: HashMap<usize, Vec<usize>> = HashMap::new();
+
+        // Original vertices on a ring are shared by the edges and the ring
+        let mut new_vertices = vec![NIL; self.vertices.len()];
+        for (i, vertex) in self.vertices.iter().enumerate() {
+            // Every corner is the first corner of a side
+            let near = grid.near((vertex.x, vertex.y), (vertex.x, vertex.y));
+            let corner = near.iter().map(|side| sides[*side]).find(|(ring, corner)| {
+                let (x, y) = rings[*ring][*corner];
+                (x - vertex.x).abs() <= tolerance && (y - vertex.y).abs() <= tolerance
+            });
+            if let Some((ring, corner)) = corner {
+                new_vertices[i] = ring_vertices[ring][corner];
+                continue;
+            }
+            for side in near {
+                let (a, b) = grid.side(side);
+                if distance_to_segment(vertex.x, vertex.y, a, b) <= tolerance {
+                    if new_vertices[i] == NIL {
+                        new_vertices[i] = clipped.create_vertex(vertex.x, vertex.y);
+                    }
+                    side_splits[side].push((distance_along(vertex.x, vertex.y, a, b), new_vertices[i]));
                 }
-                println!("Trying again to find starting edge");
             }
+        }
 
-            if should_remove_face {
-                faces_to_remove.push(i);
+        let mut vertices_by_position = HashMap::new();
+        for i in 0..(self.halfedges.len() / 2) {
+            let edge = i * 2;
+            let twin = i * 2 + 1;
+            if !self.halfedges[edge].active {
                 continue;
             }
+            let faces = [self.halfedges[edge].face, self.halfedges[twin].face];
+            let line = self.get_line(edge);
+            let (t_start, t_end) = match line.clip(region) {
+                Some(range) => range,
+                None => continue
+            };
+
+            // The ends of the part of the edge near the region, which are the
+            // original vertices unless the edge had to be clipped
+            let origin = if t_start == line.t_min { self.halfedges[edge].origin } else { NIL };
+            let end = if t_end == line.t_max { self.halfedges[twin].origin } else { NIL };
+            let point_at = |vertex: usize, t: f64| {
+                if vertex == NIL { (line.x + t * line.dx, line.y + t * line.dy) } else { self.get_vertex(vertex) }
+            };
+            let (start_x, start_y) = point_at(origin, t_start);
+            let (end_x, end_y) = point_at(end, t_end);
+            let (dx, dy) = (end_x - start_x, end_y - start_y);
+            for vertex in [origin, end].iter() {
+                if *vertex != NIL && new_vertices[*vertex] != NIL {
+                    vertex_faces.entry(new_vertices[*vertex]).or_default().extend(faces.iter());
+                }
+            }
 
-            // We now have an edge to start at
-            let mut state = 0; // Inside bbox
-            let mut edge = starting_edge;
-            let mut exiting_edge = NIL;
-            let mut out_vertex = NIL;
-            let mut exiting_side = BoundSide::Left;
-            loop {
-                if self.halfedges[edge].origin == NIL || self.halfedges[edge].next == NIL {
-                    // Only bound faces that are complete
-                    should_remove_face = true;
-                    break;
-                }
-                let vertex = &self.vertices[self.halfedges[edge].origin];
-                let next = self.halfedges[edge].next;
-                let next_vertex = &self.vertices[self.halfedges[next].origin];
-                // Note: next's origin must not be NIL because it has a previous halfedge (unless DCEL is malformed)
-
-                // Bound this line segment
-                let segment = LineSegment {
-                    start_x: vertex.x,
-                    start_y: vertex.y,
-                    end_x: next_vertex.x,
-                    end_y: next_vertex.y
+            // Find where the edge meets each side, as a fraction of the way
+            // along the edge
+            let mut splits: Vec<(f64, usize)> = Vec::new();
+            for index in grid.near((start_x.min(end_x), start_y.min(end_y)), (start_x.max(end_x), start_y.max(end_y))) {
+                let (side, side_split) = (sides[index], &mut side_splits[index]);
+                let (a, b) = grid.side(index);
+                let is_apart = start_x.max(end_x) < a.0.min(b.0) - tolerance || start_x.min(end_x) > a.0.max(b.0) + tolerance
+                    || start_y.max(end_y) < a.1.min(b.1) - tolerance || start_y.min(end_y) > a.1.max(b.1) + tolerance;
+                if is_apart {
+                    continue;
+                }
+
+                // The edge passes through a corner, which is the first corner
+                // of exactly one side
+                if distance_to_segment(a.0, a.1, (start_x, start_y), (end_x, end_y)) <= tolerance {
+                    let corner = ring_vertices[side.0][side.1];
+                    splits.push((distance_along(a.0, a.1, (start_x, start_y), (end_x, end_y)), corner));
+                    vertex_faces.entry(corner).or_default().extend(faces.iter());
+                    continue;
+                }
+                if distance_to_segment(b.0, b.1, (start_x, start_y), (end_x, end_y)) <= tolerance {
+                    continue;
+                }
+
+                // Otherwise it has to cross the side properly
+                let start_side = orient2d(a, b, (start_x, start_y));
+                let end_side = orient2d(a, b, (end_x, end_y));
+                let a_side = orient2d((start_x, start_y), (end_x, end_y), a);
+                let b_side = orient2d((start_x, start_y), (end_x, end_y), b);
+                if start_side == 0. || end_side == 0. || (start_side > 0.) == (end_side > 0.) || (a_side > 0.) == (b_side > 0.) {
+                    continue;
+                }
+                let (side_x, side_y) = (b.0 - a.0, b.1 - a.1);
+                let fraction = ((a.0 - start_x) * side_y - (a.1 - start_y) * side_x) / (dx * side_y - dy * side_x);
+                // Rounding can put the point slightly off the side, which
+                // would bend the ring
+                let (x, y) = nearest_on_segment(start_x + fraction * dx, start_y + fraction * dy, a, b);
+
+                // A crossing right at an original vertex is that vertex
+                let mut vertex = NIL;
+                for original in [origin, end].iter() {
+                    if *original != NIL {
+                        let (original_x, original_y) = self.get_vertex(*original);
+                        if (original_x - x).abs() <= tolerance && (original_y - y).abs() <= tolerance {
+                            if new_vertices[*original] == NIL {
+                                new_vertices[*original] = clipped.create_vertex(original_x, original_y);
+                            }
+                            vertex = new_vertices[*original];
+                        }
+                    }
+                }
+                if vertex == NIL {
+                    vertex = *vertices_by_position.entry((x.to_bits(), y.to_bits())).or_insert_with(|| clipped.create_vertex(x, y));
+                }
+                splits.push((fraction, vertex));
+                side_split.push((distance_along(x, y, a, b), vertex));
+                vertex_faces.entry(vertex).or_default().extend(faces.iter());
+            }
+            splits.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+            splits.dedup_by_key(|split| split.1);
+
+            // Keep the parts between the splits that are inside the region.
+            // Parts at an end that was clipped are outside of it.
+            let mut points = vec![(0., NIL)];
+            points.extend(splits);
+            points.push((1., NIL));
+            for j in 0..(points.len() - 1) {
+                let (from_fraction, mut from) = points[j];
+                let (to_fraction, mut to) = points[j + 1];
+                let is_first = j == 0;
+                let is_last = j + 2 == points.len();
+                if (is_first && origin == NIL) || (is_last && end == NIL) {
+                    continue;
+                }
+                let middle = (from_fraction + to_fraction) / 2.;
+                let (x, y) = (start_x + middle * dx, start_y + middle * dy);
+                // The region is the rings by the even-odd rule
+                if !grid.contains(x, y) || is_on_boundary(x, y) {
+                    continue;
+                }
+                for (is_end, vertex, original) in [(is_first, &mut from, origin), (is_last, &mut to, end)].iter_mut() {
+                    if *is_end {
+                        if new_vertices[*original] == NIL {
+                            let (x, y) = self.get_vertex(*original);
+                            new_vertices[*original] = clipped.create_vertex(x, y);
+                        }
+                        **vertex = new_vertices[*original];
+                    }
+                }
+                if from == to {
+                    continue;
+                }
+                let (new_edge, new_twin) = clipped.create_twins(faces[0], faces[1]);
+                clipped.set_origin(new_edge, from);
+                clipped.set_origin(new_twin, to);
+            }
+        }
+
+        // Cut the rings where the edges cross them. Each part of a ring is in
+        // the cell of one of the sites whose edges end where the part starts or
+        // ends, or else in the same cell as the part before it.
+        let mut side_index = 0;
+        for (ring, corners) in rings.iter().enumerate() {
+            let (x, y) = side_ends((ring, 0)).0;
+            let mut face = self.nearest_site(x, y, 0..self.sites.len(), corners[0], corners[1]);
+            for corner in 0..corners.len() {
+                let (a, b) = side_ends((ring, corner));
+                let mut points = vec![(0., ring_vertices[ring][corner])];
+                let mut splits = side_splits[side_index].clone();
+                side_index += 1;
+                splits.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+                points.extend(splits);
+                points.push((f64::INFINITY, ring_vertices[ring][(corner + 1) % corners.len()]));
+                points.dedup_by_key(|point| point.1);
+
+                for j in 0..(points.len() - 1) {
+                    let (from, to) = (points[j].1, points[j + 1].1);
+                    let (from_x, from_y) = clipped.get_vertex(from);
+                    let (to_x, to_y) = clipped.get_vertex(to);
+                    let (x, y) = ((from_x + to_x) / 2., (from_y + to_y) / 2.);
+                    let none = Vec::new();
+                    let candidates: Vec<usize> = vertex_faces.get(&from).unwrap_or(&none).iter()
+                        .chain(vertex_faces.get(&to).unwrap_or(&none).iter())
+                        .chain(std::iter::once(&face))
+                        .cloned()
+                        .collect();
+                    face = self.nearest_site(x, y, candidates.into_iter(), a, b);
+                    let (new_edge, new_twin) = clipped.create_twins(face, NIL);
+                    clipped.set_origin(new_edge, from);
+                    clipped.set_origin(new_twin, to);
+                }
+            }
+        }
+
+        clipped.link_rings();
+        clipped
+    }
+
+    // Limits every face to a disk around its site, with a radius for each face.
+    // Edges are cut where they leave the disks, and faces are closed by arcs
+    // around their site. Since the radii can differ, an edge can end up
+    // bounding only one of its two faces.
+    pub(crate) fn bound_to_radii(&self, radii: &[f64]) -> Dcel {
+        let mut clipped = self.empty_copy();
+        clipped.has_radii = true;
+        let is_inside = |face: usize, (x, y): (f64, f64), tolerance: f64| {
+            if face == NIL {
+                return false;
+            }
+            let (site_x, site_y) = self.sites[face];
+            (x - site_x).hypot(y - site_y) <= radii[face] + tolerance
+        };
+
+        let mut new_vertices = vec![NIL; self.vertices.len()];
+        let mut vertices_by_position = HashMap::new();
+        for i in 0..(self.halfedges.len() / 2) {
+            // Arcs are followed counterclockwise
+            let (edge, twin) = if self.halfedges[i * 2 + 1].arc.is_some() { (i * 2 + 1, i * 2) } else { (i * 2, i * 2 + 1) };
+            if !self.halfedges[edge].active {
+                continue;
+            }
+            let faces = [self.halfedges[edge].face, self.halfedges[twin].face];
+            let ends = [self.halfedges[edge].origin, self.halfedges[twin].origin];
+            let arc = self.halfedges[edge].arc;
+            let curve = match arc {
+                Some((x, y, radius)) => {
+                    let (x1, y1) = self.get_vertex(ends[0]);
+                    let (x2, y2) = self.get_vertex(ends[1]);
+                    let start = (y1 - y).atan2(x1 - x);
+                    let mut sweep = ((y2 - y).atan2(x2 - x) - start).rem_euclid(2. * std::f64::consts::PI);
+                    if sweep == 0. {
+                        sweep = 2. * std::f64::consts::PI;
+                    }
+                    Curve::Arc { x, y, radius, start, sweep }
+                },
+                None => Curve::Straight(self.get_line(edge))
+            };
+            let (low, high) = curve.range();
+            let point_at = |vertex: usize, parameter: f64| {
+                if vertex == NIL { curve.point_at(parameter) } else { self.get_vertex(vertex) }
+            };
+            let (start_x, start_y) = point_at(ends[0], low);
+            let (end_x, end_y) = point_at(ends[1], high);
+            let magnitude = [start_x, start_y, end_x, end_y].iter()
+                .filter(|coordinate| coordinate.is_finite())
+                .fold(0., |magnitude: f64, coordinate| magnitude.max(coordinate.abs()));
+
+            // Cut the edge where it crosses the circles of its faces
+            let mut splits: Vec<(f64, (f64, f64))> = Vec::new();
+            let mut tolerance = 8. * f64::EPSILON * magnitude;
+            for face in faces.iter().filter(|face| **face != NIL && radii[**face].is_finite()) {
+                let (site_x, site_y) = self.sites[*face];
+                tolerance = tolerance.max(1e-10 * radii[*face] + 8. * f64::EPSILON * magnitude);
+                for parameter in curve.crossings(site_x, site_y, radii[*face]) {
+                    if parameter > low && parameter < high {
+                        splits.push((parameter, curve.point_at(parameter)));
+                    }
+                }
+            }
+            splits.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+
+            // Both circles cross at the same point if the radii are the same,
+            // and crossings can be at the ends of the edge
+            let mut points = vec![(low, (start_x, start_y), ends[0])];
+            for (parameter, (x, y)) in splits {
+                let (_, (last_x, last_y), _) = points[points.len() - 1];
+                if (x - last_x).abs() > tolerance || (y - last_y).abs() > tolerance {
+                    points.push((parameter, (x, y), NIL));
+                }
+            }
+            let (_, (last_x, last_y), _) = points[points.len() - 1];
+            if points.len() > 1 && (end_x - last_x).abs() <= tolerance && (end_y - last_y).abs() <= tolerance {
+                points.pop();
+            }
+            points.push((high, (end_x, end_y), ends[1]));
+
+            for j in 0..(points.len() - 1) {
+                let (from_parameter, _, _) = points[j];
+                let (to_parameter, _, _) = points[j + 1];
+                if !from_parameter.is_finite() || !to_parameter.is_finite() {
+                    // Unbounded ends never close a face
+                    continue;
+                }
+                let middle = curve.point_at((from_parameter + to_parameter) / 2.);
+                let face = if is_inside(faces[0], middle, tolerance) { faces[0] } else { NIL };
+                let twin_face = if is_inside(faces[1], middle, tolerance) { faces[1] } else { NIL };
+                if face == NIL && twin_face == NIL {
+                    continue;
+                }
+
+                let mut vertex_at = |dcel: &mut Dcel, (_, (x, y), original): (f64, (f64, f64), usize)| {
+                    if original != NIL {
+                        if new_vertices[original] == NIL {
+                            new_vertices[original] = dcel.create_vertex(x, y);
+                        }
+                        return new_vertices[original];
+                    }
+                    *vertices_by_position.entry((x.to_bits(), y.to_bits())).or_insert_with(|| dcel.create_vertex(x, y))
                 };
-                let result = bound(segment, bbox);
-
-                if state == 0 {
-                    // State is INSIDE
-                    if let BoundResult::Intersect { x, y, side } = result {
-                        state = 1; // Outside
-                        println!("Leaving at {} {}", x, y);
-                        out_vertex = self.create_vertex(x, y);
-                        exiting_edge = edge;
-                        exiting_side = side;
+                let from = vertex_at(&mut clipped, points[j]);
+                let to = vertex_at(&mut clipped, points[j + 1]);
+                if from == to {
+                    continue;
+                }
+                let (new_edge, new_twin) = clipped.create_twins(face, twin_face);
+                clipped.set_origin(new_edge, from);
+                clipped.set_origin(new_twin, to);
+                if face != NIL {
+                    clipped.halfedges[new_edge].arc = arc;
+                }
+            }
+        }
+
+        // Where a face leaves its disk, it continues counterclockwise around
+        // the circle to the next point where it comes back in
+        let mut starts = HashMap::new();
+        let mut ends = HashMap::new();
+        for (i, halfedge) in clipped.halfedges.iter().enumerate() {
+            if halfedge.face != NIL {
+                starts.insert((halfedge.origin, halfedge.face), i);
+                ends.insert((clipped.halfedges[halfedge.twin].origin, halfedge.face), i);
+            }
+        }
+        let mut exits = vec![Vec::new(); self.faces.len()];
+        let mut entries = vec![Vec::new(); self.faces.len()];
+        for (vertex, face) in ends.keys() {
+            if !starts.contains_key(&(*vertex, *face)) {
+                exits[*face].push(*vertex);
+            }
+        }
+        for (vertex, face) in starts.keys() {
+            if !ends.contains_key(&(*vertex, *face)) {
+                entries[*face].push(*vertex);
+            }
+        }
+        for face in 0..self.faces.len() {
+            let (x, y) = self.sites[face];
+            let radius = radii[face];
+            if !radius.is_finite() {
+                continue;
+            }
+            let arc = Some((x, y, radius));
+            let angle = |dcel: &Dcel, vertex: usize| {
+                let (vertex_x, vertex_y) = dcel.get_vertex(vertex);
+                (vertex_y - y).atan2(vertex_x - x)
+            };
+
+            if exits[face].is_empty() {
+                // The whole circle is in the face if the face is in the disk
+                // but never crosses the circle
+                if self.is_inside_face(face, x + radius, y) {
+                    let right = clipped.create_vertex(x + radius, y);
+                    let left = clipped.create_vertex(x - radius, y);
+                    for (from, to) in [(right, left), (left, right)].iter() {
+                        let (edge, twin) = clipped.create_twins(face, NIL);
+                        clipped.set_origin(edge, *from);
+                        clipped.set_origin(twin, *to);
+                        clipped.halfedges[edge].arc = arc;
                     }
-                } else {
-                    // State is OUTSIDE
-                    println!("Looking for entrance: {:?}", result);
-                    self.halfedges[edge].active = false;
-                    if let BoundResult::Intersect { x, y, side } = result {
-                        if side != exiting_side {
-                            for (corner_x, corner_y) in corners_between(exiting_side, side, &bbox) {
-                                let corner = self.create_vertex(corner_x, corner_y);
-                                let (corner_edge, corner_edge_twin) = self.create_twins();
-                                let exiting_edge_twin = self.halfedges[exiting_edge].twin;
-                                println!("Editing edge {} w corner", exiting_edge_twin);
-                                self.halfedges[exiting_edge].next = corner_edge;
-                                self.halfedges[exiting_edge_twin].origin = out_vertex;
-                                self.halfedges[corner_edge].origin = out_vertex;
-                                self.halfedges[corner_edge_twin].origin = corner;
-                                out_vertex = corner;
-                                exiting_edge = corner_edge;
-                            }
+                }
+                continue;
+            }
+            for exit in exits[face].iter() {
+                let exit_angle = angle(&clipped, *exit);
+                let turn = |vertex: usize| {
+                    let turn = (angle(&clipped, vertex) - exit_angle).rem_euclid(2. * std::f64::consts::PI);
+                    if turn == 0. { 2. * std::f64::consts::PI } else { turn }
+                };
+                let entry = entries[face].iter().min_by(|a, b| turn(**a).partial_cmp(&turn(**b)).unwrap_or(std::cmp::Ordering::Equal));
+                if let Some(entry) = entry {
+                    let (edge, twin) = clipped.create_twins(face, NIL);
+                    clipped.set_origin(edge, *exit);
+                    clipped.set_origin(twin, *entry);
+                    clipped.halfedges[edge].arc = arc;
+                }
+            }
+        }
+
+        clipped.link_rings();
+        clipped
+    }
+
+    // Finds the face whose site is nearest to a point, walking the triangles
+    // from the last site added. Sites that are all on a line have no
+    // triangles to walk, so they're checked one by one.
+    fn nearest_face(&self, point: (f64, f64)) -> Option<usize> {
+        match (0..self.sites.len()).rev().find(|face| self.face_triangles[*face] != NIL) {
+            Some(start) => Some(walk_to_nearest(self, point, start, |face, visit| self.visit_neighbors(face, visit))),
+            None => (0..self.sites.len()).min_by(|a, b| {
+                let distance = |face: usize| (self.sites[face].0 - point.0).hypot(self.sites[face].1 - point.1);
+                distance(*a).total_cmp(&distance(*b))
+            })
+        }
+    }
+
+    // Goes through the Delaunay neighbors of a face. Each one comes after the
+    // face in one of the triangles around it, except the last one along the
+    // hull, which only comes before it.
+    fn visit_neighbors(&self, face: usize, visit: &mut dyn FnMut(usize)) {
+        self.visit_triangles_around(face, |triangle, corner| {
+            visit(self.triangles[triangle][(corner + 1) % 3]);
+            if self.triangle_neighbors[triangle][(corner + 2) % 3] == NIL {
+                visit(self.triangles[triangle][(corner + 2) % 3]);
+            }
+        });
+    }
+
+    // The triangles that have a face as a corner, see `visit_triangles_around`
+    fn triangles_around(&self, face: usize) -> Vec<usize> {
+        let mut around = Vec::new();
+        self.visit_triangles_around(face, |triangle, _| around.push(triangle));
+        around
+    }
+
+    // Goes through the triangles that have a face as a corner, along with
+    // which corner it is, turning around it from the one in `face_triangles`,
+    // and back the other way if it's on the hull
+    fn visit_triangles_around(&self, face: usize, mut visit: impl FnMut(usize, usize)) {
+        let first = self.face_triangles[face];
+        if first == NIL {
+            return;
+        }
+        let corner = |triangle: usize| self.triangles[triangle].iter().position(|other| *other == face).unwrap();
+        let first_corner = corner(first);
+        visit(first, first_corner);
+        let (mut triangle, mut at) = (first, first_corner);
+        loop {
+            triangle = self.triangle_neighbors[triangle][at];
+            if triangle == first {
+                return;
+            }
+            if triangle == NIL {
+                break;
+            }
+            at = corner(triangle);
+            visit(triangle, at);
+        }
+        triangle = self.triangle_neighbors[first][(first_corner + 2) % 3];
+        while triangle != NIL {
+            at = corner(triangle);
+            visit(triangle, at);
+            triangle = self.triangle_neighbors[triangle][(at + 2) % 3];
+        }
+    }
+
+    // The side of the hull that goes on from a face, or comes into it if
+    // `forward` is false, as a triangle and the index of the side
+    fn hull_side_at(&self, face: usize, forward: bool) -> (usize, usize) {
+        let mut triangle = self.face_triangles[face];
+        loop {
+            let corner = self.triangles[triangle].iter().position(|other| *other == face).unwrap();
+            let side = if forward { corner } else { (corner + 2) % 3 };
+            match self.triangle_neighbors[triangle][side] {
+                NIL => return (triangle, side),
+                neighbor => triangle = neighbor
+            }
+        }
+    }
+
+    // Adds the Delaunay triangles of a new site. The triangles whose
+    // circumcircles hold it, along with the outside past any side of the hull
+    // that it's beyond, are replaced by a fan of triangles from the site to
+    // the sides around them. Those make up one cavity around the site nearest
+    // to the new one, which is found by spreading out from the triangles at
+    // that site, so only the triangles near it are looked at. The slots of
+    // the triangles taken out go to the new ones. Returns the sites that the
+    // new one is joined to, which are the only ones whose cells it can take
+    // from.
+    fn insert_triangles(&mut self, site: usize, nearest: Option<usize>) -> Vec<usize> {
+        let point = self.sites[site];
+        // Each triangle of the fan, with the triangle across its side away
+        // from the site
+        let mut fan: Vec<([usize; 3], usize)> = Vec::new();
+        let first = nearest.and_then(|face| self.triangles_around(face).first().copied());
+        if first.is_none() {
+            // The other sites are all on a line, so the fan goes to each pair of
+            // them that are next to each other along it
+            let mut order: Vec<usize> = (0..site).collect();
+            order.sort_by(|a, b| self.sites[*a].partial_cmp(&self.sites[*b]).unwrap());
+            for pair in order.windows(2) {
+                let orientation = orient2d(self.sites[pair[0]], self.sites[pair[1]], point);
+                if orientation > 0. {
+                    fan.push(([pair[0], pair[1], site], NIL));
+                } else if orientation < 0. {
+                    fan.push(([pair[1], pair[0], site], NIL));
+                }
+            }
+        } else {
+            // The places that can be in the cavity are the triangles, and the
+            // outside past each side of the hull, which is given by the
+            // triangle inside that side and the index of the side
+            let is_in_cavity = |place: (usize, usize)| match place {
+                (triangle, NIL) => {
+                    let [a, b, c] = self.triangles[triangle];
+                    incircle(self.sites[a], self.sites[b], self.sites[c], point) > 0.
+                },
+                (triangle, side) => {
+                    // Whether the point is past the side (which has the inside
+                    // on its left), or on it
+                    let corners = self.triangles[triangle];
+                    let (a, b) = (self.sites[corners[side]], self.sites[corners[(side + 1) % 3]]);
+                    let orientation = orient2d(a, b, point);
+                    let is_between = (point.0 - a.0) * (b.0 - a.0) + (point.1 - a.1) * (b.1 - a.1) > 0.
+                        && (point.0 - b.0) * (a.0 - b.0) + (point.1 - b.1) * (a.1 - b.1) > 0.;
+                    orientation < 0. || (orientation == 0. && is_between)
+                }
+            };
+            let places_next_to = |place: (usize, usize)| match place {
+                (triangle, NIL) => (0..3).map(|side| match self.triangle_neighbors[triangle][side] {
+                    NIL => (triangle, side),
+                    neighbor => (neighbor, NIL)
+                }).collect::<Vec<_>>(),
+                (triangle, side) => {
+                    let corners = self.triangles[triangle];
+                    vec![
+                        (triangle, NIL),
+                        self.hull_side_at(corners[(side + 1) % 3], true),
+                        self.hull_side_at(corners[side], false)
+                    ]
+                }
+            };
+
+            // Start from the places at the nearest site, and if none of them
+            // are in the cavity (which only happens when the point is as far
+            // from another site), from anywhere
+            let mut starts: Vec<(usize, usize)> = nearest.map(|face| self.triangles_around(face)).unwrap_or_default().into_iter()
+                .flat_map(|triangle| std::iter::once((triangle, NIL)).chain(places_next_to((triangle, NIL))))
+                .filter(|place| is_in_cavity(*place))
+                .collect();
+            if starts.is_empty() {
+                starts = (0..self.triangles.len()).filter(|triangle| self.triangles[*triangle][0] != NIL)
+                    .flat_map(|triangle| std::iter::once((triangle, NIL)).chain(places_next_to((triangle, NIL))))
+                    .filter(|place| is_in_cavity(*place))
+                    .collect();
+            }
+            let mut in_cavity: HashMap<(usize, usize), bool> = starts.iter().map(|place| (*place, true)).collect();
+            let mut stack = starts;
+            let mut cavity = Vec::new();
+            while let Some(place) = stack.pop() {
+                cavity.push(place);
+                for next in places_next_to(place) {
+                    if let Entry::Vacant(entry) = in_cavity.entry(next) {
+                        if *entry.insert(is_in_cavity(next)) {
+                            stack.push(next);
+                        }
+                    }
+                }
+            }
+            cavity.sort_unstable();
+            cavity.dedup();
+
+            for &(triangle, side) in &cavity {
+                let corners = self.triangles[triangle];
+                if side == NIL {
+                    for side in 0..3 {
+                        let (a, b) = (corners[side], corners[(side + 1) % 3]);
+                        match self.triangle_neighbors[triangle][side] {
+                            NIL if !in_cavity[&(triangle, side)] => fan.push(([a, b, site], NIL)),
+                            NIL => (),
+                            neighbor if !in_cavity[&(neighbor, NIL)] => fan.push(([a, b, site], neighbor)),
+                            _ => ()
                         }
-                        println!("Entering at {} {}", x, y);
-                        let in_vertex = self.create_vertex(x, y);
-                        let (bbox_edge, bbox_edge_twin) = self.create_twins();
-                        let (entering_edge, entering_edge_twin) = self.create_twins();
-                        let exiting_edge_twin = self.halfedges[exiting_edge].twin;
-                        self.halfedges[bbox_edge].origin = out_vertex;
-                        self.halfedges[bbox_edge_twin].origin = in_vertex;
-                        self.halfedges[bbox_edge].next = entering_edge;
-                        self.halfedges[exiting_edge].next = bbox_edge;
-                        self.halfedges[exiting_edge_twin].origin = out_vertex;
-                        self.halfedges[entering_edge].origin = in_vertex;
-                        self.halfedges[entering_edge].next = next;
-                        self.halfedges[entering_edge_twin].origin = self.halfedges[next].origin;
-                        self.faces[i] = entering_edge;
-                        break;
                     }
+                } else if !in_cavity[&(triangle, NIL)] {
+                    fan.push(([corners[(side + 1) % 3], corners[side], site], triangle));
                 }
+            }
+            for &(triangle, side) in &cavity {
+                if side == NIL {
+                    self.triangles[triangle] = [NIL; 3];
+                    self.triangle_neighbors[triangle] = [NIL; 3];
+                    self.free_triangles.push(triangle);
+                }
+            }
+        }
 
-                edge = next;
-                if edge == starting_edge {
-                    // We're back to the beginning, we're done
-                    println!("Back to beginning");
-                    break;
+        // Put the fan in the free slots and join it up, with the triangles
+        // around it and with each other
+        let slots: Vec<usize> = fan.iter().map(|_| match self.free_triangles.pop() {
+            Some(slot) => slot,
+            None => {
+                self.triangles.push([NIL; 3]);
+                self.triangle_neighbors.push([NIL; 3]);
+                self.triangles.len() - 1
+            }
+        }).collect();
+        let starting_at: HashMap<usize, usize> = fan.iter().zip(&slots).map(|((corners, _), slot)| (corners[0], *slot)).collect();
+        let ending_at: HashMap<usize, usize> = fan.iter().zip(&slots).map(|((corners, _), slot)| (corners[1], *slot)).collect();
+        for ((corners, outside), slot) in fan.iter().zip(&slots) {
+            let [a, b, _] = *corners;
+            self.triangles[*slot] = *corners;
+            self.triangle_neighbors[*slot] = [
+                *outside,
+                starting_at.get(&b).copied().unwrap_or(NIL),
+                ending_at.get(&a).copied().unwrap_or(NIL)
+            ];
+            if *outside != NIL {
+                let side = self.triangles[*outside].iter().position(|corner| *corner == b).unwrap();
+                self.triangle_neighbors[*outside][side] = *slot;
+            }
+            for corner in corners {
+                self.face_triangles[*corner] = *slot;
+            }
+        }
+
+        let mut neighbors: Vec<usize> = fan.iter().flat_map(|(corners, _)| corners[..2].to_vec()).collect();
+        if fan.is_empty() && first.is_none() {
+            // Still all on a line
+            neighbors = (0..site).collect();
+        }
+        neighbors.sort_unstable();
+        neighbors.dedup();
+        neighbors
+    }
+
+    // Carves the face of a new site out of the faces of its neighbors. Each one
+    // is cut along the bisector between its site and the new one, and the
+    // halfedges on the far side of it go to the new face, or are dropped if
+    // they're between two of the neighbors. The cuts close both faces off.
+    fn carve_face(&mut self, site: usize, neighbors: &[usize]) -> Result<()> {
+        let point = self.sites[site];
+        let neighbors: Vec<usize> = neighbors.iter().copied().filter(|face| self.faces[*face] != NIL).collect();
+        let is_neighbor = |face: usize| face != NIL && neighbors.binary_search(&face).is_ok();
+
+        for face in &neighbors {
+            let halfedges: Vec<usize> = self.face_halfedges(FaceId(*face)).map(|halfedge| halfedge.0).collect();
+            for halfedge in halfedges {
+                self.split_at_bisector(halfedge, *face, point);
+            }
+        }
+
+        // Sort out which halfedges are on the new site's side, going by their
+        // middle. The two sides of an edge between neighbors go together.
+        let mut halfedges_by_face = Vec::with_capacity(neighbors.len());
+        let mut is_removed: HashMap<usize, bool> = HashMap::new();
+        for face in &neighbors {
+            let halfedges: Vec<usize> = self.face_halfedges(FaceId(*face)).map(|halfedge| halfedge.0).collect();
+            for halfedge in &halfedges {
+                let twin = self.get_twin(*halfedge);
+                let removed = match is_removed.get(&twin) {
+                    Some(removed) => *removed,
+                    None => self.is_past_bisector(*halfedge, *face, point)
+                };
+                is_removed.insert(*halfedge, removed);
+            }
+            halfedges_by_face.push(halfedges);
+        }
+
+        // Close each neighbor along the bisector, from where its boundary
+        // crosses over to the new site's side to where it comes back. The
+        // twins of those halfedges go around the new face.
+        let mut touched: Vec<usize> = halfedges_by_face.iter().flatten().copied().collect();
+        for (face, halfedges) in neighbors.iter().zip(&halfedges_by_face) {
+            let mut crossings = Vec::new();
+            for halfedge in halfedges {
+                let next = self.halfedges[*halfedge].next;
+                match is_removed.get(&next) {
+                    Some(next_removed) if *next_removed != is_removed[halfedge] => {
+                        crossings.push((self.halfedges[next].origin, !is_removed[halfedge]));
+                    },
+                    _ => ()
+                }
+            }
+            let (site_x, site_y) = self.sites[*face];
+            let along = |vertex: usize| {
+                let (x, y) = self.get_vertex(vertex);
+                (y - site_y) * (point.0 - site_x) - (x - site_x) * (point.1 - site_y)
+            };
+            crossings.sort_by(|a, b| along(a.0).partial_cmp(&along(b.0)).unwrap_or(std::cmp::Ordering::Equal));
+            if crossings.len() % 2 != 0 {
+                return invariant(format!("face {} crosses the bisector an odd number of times", face));
+            }
+            for pair in crossings.chunks(2) {
+                let (from, to) = match (pair[0], pair[1]) {
+                    ((from, true), (to, false)) | ((to, false), (from, true)) => (from, to),
+                    _ => return invariant(format!("face {} crosses the bisector the same way twice in a row", face))
+                };
+                if from != to {
+                    let (edge, twin) = self.create_twins(*face, site);
+                    self.set_origin(edge, from);
+                    self.set_origin(twin, to);
+                    touched.push(edge);
+                    touched.push(twin);
+                }
+            }
+        }
+        for (halfedge, removed) in &is_removed {
+            if *removed {
+                if is_neighbor(self.halfedges[self.get_twin(*halfedge)].face) {
+                    self.halfedges[*halfedge].active = false;
                 } else {
-                    println!("Looking at edge {}", edge);
+                    self.halfedges[*halfedge].face = site;
+                }
+            }
+        }
+
+        // Link up the halfedges of the faces that changed, and find their rings
+        let is_changed = |dcel: &Dcel, halfedge: usize| {
+            let face = dcel.halfedges[halfedge].face;
+            dcel.halfedges[halfedge].active && (face == site || is_neighbor(face))
+        };
+        let mut outgoing: HashMap<(usize, usize), Vec<usize>> = HashMap::new();
+        for halfedge in &touched {
+            if is_changed(self, *halfedge) {
+                outgoing.entry((self.halfedges[*halfedge].origin, self.halfedges[*halfedge].face)).or_default().push(*halfedge);
+            }
+        }
+        for halfedge in &touched {
+            if is_changed(self, *halfedge) {
+                let end = self.get_origin(self.get_twin(*halfedge));
+                let next = outgoing.get(&(end, self.halfedges[*halfedge].face)).and_then(|candidates| self.get_turn(*halfedge, candidates));
+                self.set_next(*halfedge, next.unwrap_or(NIL));
+            }
+        }
+
+        // Where an edge between two neighbors was taken away, the edges left
+        // on either side of its end can carry straight on between the same
+        // two faces, and then the vertex between them goes too
+        let mut ends: Vec<usize> = is_removed.iter()
+            .filter(|(halfedge, removed)| **removed && !self.halfedges[**halfedge].active)
+            .map(|(halfedge, _)| self.halfedges[*halfedge].origin)
+            .collect();
+        ends.sort_unstable();
+        ends.dedup();
+        let mut leaving: HashMap<usize, Vec<usize>> = ends.iter().map(|vertex| (*vertex, Vec::new())).collect();
+        for halfedge in &touched {
+            for halfedge in [*halfedge, self.get_twin(*halfedge)] {
+                if let Some(leaving) = leaving.get_mut(&self.halfedges[halfedge].origin) {
+                    if self.halfedges[halfedge].active && !leaving.contains(&halfedge) {
+                        leaving.push(halfedge);
+                    }
                 }
             }
+        }
+        for vertex in ends {
+            if let [a, b] = leaving[&vertex][..] {
+                if let Some(kept) = self.dissolve_vertex(vertex, a, b) {
+                    // The far end can be next, with the kept halfedge leaving
+                    // it in place of the one that went
+                    let removed = self.get_twin(a);
+                    if let Some(leaving) = leaving.get_mut(&self.halfedges[kept].origin) {
+                        leaving.iter_mut().filter(|halfedge| **halfedge == removed).for_each(|halfedge| *halfedge = kept);
+                    }
+                    touched.push(kept);
+                    touched.push(self.get_twin(kept));
+                }
+            }
+        }
 
-            if should_remove_face {
-                faces_to_remove.push(i);
+        let mut rings: HashMap<usize, Vec<(f64, usize)>> = std::iter::once(site).chain(neighbors.iter().copied())
+            .map(|face| (face, Vec::new()))
+            .collect();
+        let mut visited = HashSet::new();
+        for halfedge in &touched {
+            if !is_changed(self, *halfedge) || visited.contains(halfedge) {
                 continue;
             }
+            let mut current = *halfedge;
+            let mut ring = Vec::new();
+            while current != NIL && visited.insert(current) {
+                ring.push(self.get_vertex(self.halfedges[current].origin));
+                current = self.halfedges[current].next;
+            }
+            if current == *halfedge {
+                rings.get_mut(&self.halfedges[current].face).unwrap().push((signed_area(&ring), current));
+            }
         }
-        for face in faces_to_remove {
-            self.faces[face] = NIL;
+        for (face, mut face_rings) in rings {
+            face_rings.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+            let mut starts = face_rings.into_iter().map(|(_, halfedge)| halfedge);
+            self.faces[face] = starts.next().unwrap_or(NIL);
+            self.other_rings[face] = starts.collect();
+        }
+
+        // Vertices keep a halfedge that's still there, if they have one
+        for halfedge in &touched {
+            let vertex = self.halfedges[*halfedge].origin;
+            let current = self.vertices[vertex].halfedge;
+            if current != NIL && (!self.halfedges[current].active || self.halfedges[current].origin != vertex) {
+                self.vertices[vertex].halfedge = NIL;
+            }
         }
+        for halfedge in &touched {
+            if self.halfedges[*halfedge].active {
+                self.set_origin(*halfedge, self.halfedges[*halfedge].origin);
+            }
+        }
+        Ok(())
     }
 
-    // // Repairs edges that are missing origins or nexts (i.e. the rays)
-    // pub fn repair_edges(&mut self) {
-    //     let mut to_repair = vec![];
-    //     for (i, halfedge) in self.halfedges.iter().enumerate() {
-    //         if halfedge.origin == NIL || halfedge.next == NIL {
-    //             to_repair.push(i);
-    //         }
-    //     }
+    // Joins the only two edges at a vertex into one, if they're between the
+    // same two faces and carry straight on (or along the same arc). Returns
+    // the halfedge that's kept, which now leaves from the other end of the
+    // edge that's taken away.
+    fn dissolve_vertex(&mut self, vertex: usize, a: usize, b: usize) -> Option<usize> {
+        let (a_twin, b_twin) = (self.get_twin(a), self.get_twin(b));
+        let face = |halfedge: usize| self.halfedges[halfedge].face;
+        let is_chain = self.halfedges[a_twin].next == b && self.halfedges[b_twin].next == a
+            && face(a) == face(b_twin) && face(b) == face(a_twin);
+        let (from, to) = (self.get_origin(b_twin), self.get_origin(a_twin));
+        if !is_chain || from == to || from == NIL || to == NIL {
+            return None;
+        }
+        let is_straight = match (self.halfedges[b_twin].arc, self.halfedges[a].arc, self.halfedges[b].arc, self.halfedges[a_twin].arc) {
+            (None, None, None, None) => {
+                let ((x, y), start, end) = (self.get_vertex(vertex), self.get_vertex(from), self.get_vertex(to));
+                let magnitude = start.0.abs().max(start.1.abs()).max(end.0.abs()).max(end.1.abs());
+                distance_to_segment(x, y, start, end) <= tolerance_for((end.0 - start.0).hypot(end.1 - start.1), magnitude)
+            },
+            (first, second, third, fourth) => first == second && third == fourth
+        };
+        if !is_straight {
+            return None;
+        }
 
-    //     for i in to_repair {
-    //         if self.halfedges[i].next == NIL {
+        // Keep b and its twin, which now run between the far ends
+        let (after, before) = (self.halfedges[a].next, self.halfedges[a_twin].prev);
+        self.set_next(b_twin, after);
+        if before != NIL {
+            self.set_next(before, b);
+        }
+        self.halfedges[b].origin = to;
+        self.halfedges[a].active = false;
+        self.halfedges[a_twin].active = false;
+        for (removed, kept) in [(a, b_twin), (a_twin, b)] {
+            let face = self.halfedges[removed].face;
+            if face != NIL {
+                let rings = std::iter::once(&mut self.faces[face]).chain(self.other_rings[face].iter_mut());
+                for start in rings.filter(|start| **start == removed) {
+                    *start = kept;
+                }
+            }
+        }
+        self.vertices[vertex].halfedge = NIL;
+        Some(b)
+    }
 
-    //         }
-    //     }
-    // }
-}
+    // How much nearer a point is to a new site than to the site of a face,
+    // scaled. Positive on the new site's side of their bisector, and linear in
+    // the point.
+    fn get_bisector_side(&self, face: usize, point: (f64, f64), (x, y): (f64, f64)) -> f64 {
+        let (site_x, site_y) = self.sites[face];
+        let (dx, dy) = (point.0 - site_x, point.1 - site_y);
+        2. * ((x - site_x) * dx + (y - site_y) * dy) - (dx * dx + dy * dy)
+    }
 
-pub struct BoundingBox {
-    min_x: f64,
-    min_y: f64,
-    max_x: f64,
-    max_y: f64
-}
+    // Whether a halfedge of a face is on the new site's side of the bisector
+    // between them. A halfedge along the bisector is if the face is on that
+    // side of it.
+    fn is_past_bisector(&self, halfedge: usize, face: usize, point: (f64, f64)) -> bool {
+        let start = self.get_vertex(self.halfedges[halfedge].origin);
+        let end = self.get_vertex(self.get_origin(self.get_twin(halfedge)));
+        let site = self.sites[face];
+        let distance = (point.0 - site.0).hypot(point.1 - site.1);
+        let magnitude = start.0.abs().max(start.1.abs()).max(end.0.abs()).max(end.1.abs());
+        let tolerance = tolerance_for((end.0 - start.0).hypot(end.1 - start.1) + distance, magnitude) * distance;
+        let side = self.get_bisector_side(face, point, self.get_midpoint(halfedge));
+        if side.abs() > tolerance {
+            return side > 0.;
+        }
+        // The face is on the left
+        (start.1 - end.1) * (point.0 - site.0) + (end.0 - start.0) * (point.1 - site.1) > 0.
+    }
 
-impl BoundingBox {
-    pub fn new(min_x: f64, min_y: f64, max_x: f64, max_y: f64) -> BoundingBox {
-        BoundingBox {
-            min_x,
-            min_y,
-            max_x,
-            max_y
+    // The point halfway along a halfedge, or along its arc
+    fn get_midpoint(&self, halfedge: usize) -> (f64, f64) {
+        match self.halfedges[halfedge].arc {
+            Some((x, y, radius)) => {
+                let (start, sweep) = self.get_arc_angles(halfedge, (x, y));
+                let angle = start + sweep / 2.;
+                (x + radius * angle.cos(), y + radius * angle.sin())
+            },
+            None => {
+                let (x1, y1) = self.get_vertex(self.halfedges[halfedge].origin);
+                let (x2, y2) = self.get_vertex(self.get_origin(self.get_twin(halfedge)));
+                ((x1 + x2) / 2., (y1 + y2) / 2.)
+            }
         }
     }
 
-    pub fn width(&self) -> f64 {
-        self.max_x - self.min_x
+    // Splits a halfedge (and its twin) wherever it crosses the bisector between
+    // the site of a face and a new site. Crossings right by its ends are left
+    // for the ends to stand in for.
+    fn split_at_bisector(&mut self, halfedge: usize, face: usize, point: (f64, f64)) {
+        let start = self.get_vertex(self.halfedges[halfedge].origin);
+        let end = self.get_vertex(self.get_origin(self.get_twin(halfedge)));
+        let site = self.sites[face];
+        let size = (end.0 - start.0).hypot(end.1 - start.1) + (point.0 - site.0).hypot(point.1 - site.1);
+        let magnitude = start.0.abs().max(start.1.abs()).max(end.0.abs()).max(end.1.abs());
+        let tolerance = tolerance_for(size, magnitude);
+        let is_apart = |(x, y): (f64, f64)| (x - start.0).hypot(y - start.1) > tolerance && (x - end.0).hypot(y - end.1) > tolerance;
+
+        match self.halfedges[halfedge].arc {
+            None => {
+                let from = self.get_bisector_side(face, point, start);
+                let to = self.get_bisector_side(face, point, end);
+                if (from < 0. && to > 0.) || (from > 0. && to < 0.) {
+                    let t = from / (from - to);
+                    let crossing = (start.0 + t * (end.0 - start.0), start.1 + t * (end.1 - start.1));
+                    if is_apart(crossing) {
+                        let vertex = self.create_vertex(crossing.0, crossing.1);
+                        self.split_halfedge(halfedge, vertex);
+                    }
+                }
+            },
+            Some((x, y, radius)) => {
+                // The side is a + b cos(angle) + c sin(angle) around the circle
+                let (start_angle, sweep) = self.get_arc_angles(halfedge, (x, y));
+                let (dx, dy) = (point.0 - site.0, point.1 - site.1);
+                let a = self.get_bisector_side(face, point, (x, y));
+                let (b, c) = (2. * radius * dx, 2. * radius * dy);
+                let length = b.hypot(c);
+                if length == 0. || a.abs() >= length {
+                    return;
+                }
+                let middle = c.atan2(b);
+                let offset = (-a / length).acos();
+                let mut angles: Vec<f64> = [middle - offset, middle + offset].iter()
+                    .map(|angle| (angle - start_angle).rem_euclid(2. * std::f64::consts::PI))
+                    .filter(|angle| *angle < sweep)
+                    .collect();
+                angles.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                let mut rest = halfedge;
+                for angle in angles {
+                    let crossing = (x + radius * (start_angle + angle).cos(), y + radius * (start_angle + angle).sin());
+                    if is_apart(crossing) {
+                        let vertex = self.create_vertex(crossing.0, crossing.1);
+                        rest = self.split_halfedge(rest, vertex);
+                    }
+                }
+            }
+        }
     }
 
-    pub fn height(&self) -> f64 {
-        self.max_y - self.min_y
+    // Splits a halfedge in two at a vertex, along with its twin, keeping both
+    // linked into their rings. Returns the new halfedge, which is the second
+    // part of the old one.
+    fn split_halfedge(&mut self, halfedge: usize, vertex: usize) -> usize {
+        let twin = self.get_twin(halfedge);
+        let end = self.get_origin(twin);
+        let (second, twin_first) = self.create_twins(self.halfedges[halfedge].face, self.halfedges[twin].face);
+        self.halfedges[second].arc = self.halfedges[halfedge].arc;
+        self.halfedges[twin_first].arc = self.halfedges[twin].arc;
+        self.halfedges[twin].origin = vertex;
+        if self.vertices[end].halfedge == twin {
+            self.vertices[end].halfedge = twin_first;
+        }
+        self.set_origin(second, vertex);
+        self.set_origin(twin_first, end);
+        self.set_origin(twin, vertex);
+
+        let next = self.halfedges[halfedge].next;
+        self.set_next(halfedge, second);
+        if next != NIL {
+            self.set_next(second, next);
+        }
+        let prev = self.halfedges[twin].prev;
+        if prev != NIL {
+            self.set_next(prev, twin_first);
+        }
+        self.set_next(twin_first, twin);
+        second
     }
 
-    pub fn mid_x(&self) -> f64 {
-        (self.max_x + self.min_x) / 2.
+    // Whether a point is inside any part of a face, but not in one of its
+    // holes
+    fn is_inside_face(&self, face: usize, x: f64, y: f64) -> bool {
+        let rings = std::iter::once(&self.faces[face]).chain(self.other_rings[face].iter())
+            .filter_map(|halfedge| self.get_ring(*halfedge));
+        rings.filter(|ring| ring_contains(ring, x, y)).count() % 2 == 1
     }
 
-    pub fn mid_y(&self) -> f64 {
-        (self.max_y + self.min_y) / 2.
+    // Returns the face of the site nearest to a point on the side from a to b.
+    // Sites about as near as each other are on the bisector of their cells,
+    // which can run along the side, so the one on the inside of the side wins.
+    fn nearest_site(&self, x: f64, y: f64, faces: impl Iterator<Item = usize>, a: (f64, f64), b: (f64, f64)) -> usize {
+        let tolerance = 1e-10 * (b.0 - a.0).hypot(b.1 - a.1) + 8. * f64::EPSILON * x.abs().max(y.abs());
+        let mut nearest = NIL;
+        let mut nearest_distance = f64::INFINITY;
+        for face in faces {
+            let (site_x, site_y) = self.sites[face];
+            let distance = (site_x - x).hypot(site_y - y);
+            let is_tied = (distance - nearest_distance).abs() <= tolerance;
+            let is_inside = || orient2d(a, b, (site_x, site_y)) > orient2d(a, b, self.sites[nearest]);
+            if (distance < nearest_distance && !is_tied) || (is_tied && is_inside()) {
+                nearest = face;
+                nearest_distance = distance;
+            }
+        }
+        nearest
     }
-}
 
-#[derive(Debug)]
-struct LineSegment {
-    start_x: f64,
-    start_y: f64,
-    end_x: f64,
-    end_y: f64
-}
+    // Links every halfedge to the next one around its face, and finds the rings
+    // that they form. A face that touches itself at a vertex continues around
+    // the same corner, i.e. with the first edge clockwise from the way back.
+    fn link_rings(&mut self) {
+        self.link_halfedges(false);
+        // The largest counterclockwise ring of each face comes first
+        let mut rings: Vec<Vec<(f64, usize)>> = vec![Vec::new(); self.faces.len()];
+        let mut visited = vec![false; self.halfedges.len()];
+        for i in 0..self.halfedges.len() {
+            let face = self.halfedges[i].face;
+            if face == NIL || visited[i] {
+                continue;
+            }
+            let mut halfedge = i;
+            let mut area = 0.;
+            while halfedge != NIL && !visited[halfedge] {
+                visited[halfedge] = true;
+                let (x1, y1) = self.get_vertex(self.halfedges[halfedge].origin);
+                let (x2, y2) = self.get_vertex(self.get_origin(self.get_twin(halfedge)));
+                area += x1 * y2 - x2 * y1;
+                halfedge = self.halfedges[halfedge].next;
+            }
+            if halfedge == i {
+                rings[face].push((area, i));
+            }
+        }
+        for (face, mut face_rings) in rings.into_iter().enumerate() {
+            face_rings.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+            let mut halfedges = face_rings.into_iter().map(|(_, halfedge)| halfedge);
+            self.faces[face] = halfedges.next().unwrap_or(NIL);
+            self.other_rings[face] = halfedges.collect();
+        }
+    }
 
-fn is_inside(x: f64, y: f64, bbox: &BoundingBox) -> bool {
-    if x < bbox.min_x || x > bbox.max_x || y < bbox.min_y || y > bbox.max_y {
-        false
-    } else {
-        true
+    // Links halfedges to the next one around their face, or only those outside
+    // the diagram. The outside is linked like a face too, so that the next
+    // halfedge after any twin is the next one clockwise around its vertex.
+    fn link_halfedges(&mut self, outside_only: bool) {
+        let mut outgoing: HashMap<(usize, usize), Vec<usize>> = HashMap::new();
+        for (i, halfedge) in self.halfedges.iter().enumerate() {
+            if halfedge.active && halfedge.origin != NIL {
+                outgoing.entry((halfedge.origin, halfedge.face)).or_default().push(i);
+            }
+        }
+        for i in 0..self.halfedges.len() {
+            let face = self.halfedges[i].face;
+            if (outside_only && face != NIL) || self.halfedges[i].origin == NIL {
+                continue;
+            }
+            let end = self.get_origin(self.get_twin(i));
+            let candidates = match outgoing.get(&(end, face)) {
+                Some(candidates) => candidates,
+                None => continue
+            };
+            if let Some(next) = self.get_turn(i, candidates) {
+                self.set_next(i, next);
+            }
+        }
     }
-}
 
-#[derive(PartialEq, Debug)]
-enum BoundResult {
-    Inside,
-    Outside,
-    Intersect { x: f64, y: f64, side: BoundSide },
-    // DoubleIntersect { x1: f64, y1: f64, x2: f64, y2: f64, side1: BoundSide, side2: BoundSide }
-}
-#[derive(PartialEq, Clone, Copy, Debug)]
-enum BoundSide {
-    Left,
-    Right,
-    Top,
-    Bottom
-}
+    // Picks the halfedge to follow a halfedge with out of the ones leaving its
+    // end: the first one clockwise from the way back
+    fn get_turn(&self, halfedge: usize, candidates: &[usize]) -> Option<usize> {
+        let angle = |from: usize, to: usize| {
+            let (x1, y1) = self.get_vertex(from);
+            let (x2, y2) = self.get_vertex(to);
+            (y2 - y1).atan2(x2 - x1)
+        };
+        let end = self.get_origin(self.get_twin(halfedge));
+        let back = angle(end, self.halfedges[halfedge].origin);
+        let turn = |candidate: usize| {
+            let turn = (back - angle(end, self.get_origin(self.get_twin(candidate)))).rem_euclid(2. * std::f64::consts::PI);
+            if turn == 0. { 2. * std::f64::consts::PI } else { turn }
+        };
+        candidates.iter().copied().min_by(|a, b| turn(*a).partial_cmp(&turn(*b)).unwrap_or(std::cmp::Ordering::Equal))
+    }
 
-fn corners_between(side1: BoundSide, side2: BoundSide, bbox: &BoundingBox) -> Vec<(f64, f64)> {
-    let bottom_left = (bbox.min_x, bbox.min_y);
-    let bottom_right = (bbox.max_x, bbox.min_y);
-    let top_right = (bbox.max_x, bbox.max_y);
-    let top_left = (bbox.min_x, bbox.max_y);
-    match (side1, side2) {
-        (BoundSide::Top, BoundSide::Left) => vec![top_left],
-        (BoundSide::Top, BoundSide::Bottom) => vec![top_left, bottom_left],
-        (BoundSide::Top, BoundSide::Right) => vec![top_left, bottom_left, bottom_right],
-        (BoundSide::Left, BoundSide::Bottom) => vec![bottom_left],
-        (BoundSide::Left, BoundSide::Right) => vec![bottom_left, bottom_right],
-        (BoundSide::Left, BoundSide::Top) => vec![bottom_left, bottom_right, top_right],
-        (BoundSide::Bottom, BoundSide::Right) => vec![bottom_right],
-        (BoundSide::Bottom, BoundSide::Top) => vec![bottom_right, top_right],
-        (BoundSide::Bottom, BoundSide::Left) => vec![bottom_right, top_right, top_left],
-        (BoundSide::Right, BoundSide::Top) => vec![top_right],
-        (BoundSide::Right, BoundSide::Left) => vec![top_right, top_left],
-        (BoundSide::Right, BoundSide::Bottom) => vec![top_right, top_left, bottom_left],
-        _ => vec![]
+    fn is_vertex_inside(&self, vertex: usize, region: &dyn ClipRegion) -> bool {
+        vertex != NIL && region.contains(self.vertices[vertex].x, self.vertices[vertex].y)
     }
-}
 
-fn bound(segment: LineSegment, bbox: &BoundingBox) -> BoundResult {
-    // Case 1: Returns Some(segment) if segment is entirely inside box
-    // Case 2: Returns None if segment is entirely outside box
-    // Cases 3 and 4: Returns Some(new_segment) where new_segment is the portion of
-    // segment that is inside the box
-
-
-
-    let starts_inside = is_inside(segment.start_x, segment.start_y, bbox);
-    let ends_inside = is_inside(segment.end_x, segment.end_y, bbox);
-    println!("{} {} Starts: {}, {} {} Ends: {}", segment.start_x, segment.start_y, starts_inside, segment.end_x, segment.end_y, ends_inside);
-
-    if starts_inside && ends_inside {
-        // Case 1: completely inside
-        BoundResult::Inside
-    } else if !starts_inside && !ends_inside {
-        // Case 2: completely outside
-        BoundResult::Outside
-    } else if starts_inside && !ends_inside {
-        // Case 3: starts inside and ends outside
-        let dx = segment.end_x - segment.start_x;
-        let dy = segment.end_y - segment.start_y;
-
-        // Find the bounds to test against
-        let test_x = if dx < 0. { bbox.min_x } else { bbox.max_x };
-        let test_y = if dy < 0. { bbox.min_y } else { bbox.max_y };
-
-        let tx = if equals_with_epsilon(dx, 0.) { std::f64::MAX } else { (test_x - segment.start_x) / dx };
-        let ty = if equals_with_epsilon(dy, 0.) { std::f64::MAX } else { (test_y - segment.start_y) / dy };
-
-        let tmin = if tx < ty { tx } else { ty };
-        let end_x = segment.start_x + dx * tmin;
-        let end_y = segment.start_y + dy * tmin;
-
-        let side = if dx < 0. && dy < 0. {
-            if tx < ty { BoundSide::Left } else { BoundSide::Bottom }
-        } else if dx < 0. && dy > 0. {
-            if tx < ty { BoundSide::Left } else { BoundSide::Top }
-        } else if dx > 0. && dy > 0. {
-            if tx < ty { BoundSide::Right } else { BoundSide::Top }
-        } else /*if dx > 0. && dy < 0.*/ {
-            if tx < ty { BoundSide::Right } else { BoundSide::Bottom }
-        };
+    // Creates halfedges from each vertex in the path to the next one and then on
+    // to `end`, linked one after the other. Their twins are outside the diagram.
+    // They're arcs if the boundary is a circle. Returns the first of the new
+    // halfedges.
+    fn create_boundary_path(&mut self, face: usize, path: &[usize], end: usize, arc: Option<(f64, f64, f64)>) -> usize {
+        let first = self.halfedges.len();
+        for (i, vertex) in path.iter().enumerate() {
+            let next_vertex = if i + 1 < path.len() { path[i + 1] } else { end };
+            let (edge, twin) = self.create_twins(face, NIL);
+            self.set_origin(edge, *vertex);
+            self.set_origin(twin, next_vertex);
+            self.halfedges[edge].arc = arc;
+            if i > 0 {
+                self.set_next(edge - 2, edge);
+            }
+        }
+        // A path around the whole region ends where it started
+        if end == path[0] {
+            let last = self.halfedges.len() - 2;
+            self.set_next(last, first);
+        }
+        first
+    }
 
-        BoundResult::Intersect { x: end_x, y: end_y, side }
-    } else if !starts_inside && ends_inside {
-        // Case 4: starts outside and ends inside
-        // Just do case 3 backward
-        let dx = segment.start_x - segment.end_x;
-        let dy = segment.start_y - segment.end_y;
-
-        // Find the bounds to test against
-        let test_x = if dx < 0. { bbox.min_x } else { bbox.max_x };
-        let test_y = if dy < 0. { bbox.min_y } else { bbox.max_y };
-
-        let tx = if equals_with_epsilon(dx, 0.) { std::f64::MAX } else { (test_x - segment.end_x) / dx };
-        let ty = if equals_with_epsilon(dy, 0.) { std::f64::MAX } else { (test_y - segment.end_y) / dy };
-
-        let tmax = if tx < ty { tx } else { ty };
-        let start_x = segment.end_x + dx * tmax;
-        let start_y = segment.end_y + dy * tmax;
-
-        let side = if dx < 0. && dy < 0. {
-            if tx < ty { BoundSide::Left } else { BoundSide::Bottom }
-        } else if dx < 0. && dy > 0. {
-            if tx < ty { BoundSide::Left } else { BoundSide::Top }
-        } else if dx > 0. && dy > 0. {
-            if tx < ty { BoundSide::Right } else { BoundSide::Top }
-        } else /*if dx > 0. && dy < 0.*/ {
-            if tx < ty { BoundSide::Right } else { BoundSide::Bottom }
-        };
+    // Lists the active halfedges of every face, in order. Faces of sites on the
+    // convex hull aren't closed, so their halfedges start at the one coming in
+    // from infinity. If all sites are collinear, a face can also be a strip
+    // between two unconnected lines.
+    fn get_face_halfedges(&self) -> Vec<Vec<usize>> {
+        let mut faces = vec![Vec::new(); self.faces.len()];
+        let mut visited = vec![false; self.halfedges.len()];
+        // Walk the open chains first, so that each one is walked from its start
+        let starts = (0..self.halfedges.len()).filter(|halfedge| self.halfedges[*halfedge].prev == NIL)
+            .chain(0..self.halfedges.len());
+        for start in starts {
+            if visited[start] || !self.halfedges[start].active || self.halfedges[start].face == NIL {
+                continue;
+            }
+            let face = self.halfedges[start].face;
+            let mut halfedge = start;
+            while halfedge != NIL && !visited[halfedge] {
+                visited[halfedge] = true;
+                faces[face].push(halfedge);
+                halfedge = self.halfedges[halfedge].next;
+            }
+        }
+        faces
+    }
+
+    // Returns the line that a halfedge lies on, along with the range of the
+    // line that's part of the edge. Voronoi edges lie on the bisector of their
+    // two sites, which is measured from the midpoint of the sites so that far
+    // away vertices (of nearly collinear sites) don't cost any precision near
+    // the sites. Ends without a vertex extend to infinity.
+    fn get_line(&self, halfedge: usize) -> Line {
+        let twin = self.halfedges[halfedge].twin;
+        let origin = self.halfedges[halfedge].origin;
+        let end = self.halfedges[twin].origin;
+        let face = self.halfedges[halfedge].face;
+        let twin_face = self.halfedges[twin].face;
+
+        if face == NIL || twin_face == NIL {
+            // Not between two sites, so it can only be a segment
+            let from = &self.vertices[origin];
+            let to = &self.vertices[end];
+            return Line { x: from.x, y: from.y, dx: to.x - from.x, dy: to.y - from.y, t_min: 0., t_max: 1. };
+        }
 
-        BoundResult::Intersect { x: start_x, y: start_y, side }
-    } else {
-        // Impossible
-        BoundResult::Outside
+        // The face is on the left of the halfedge
+        let (x1, y1) = self.sites[face];
+        let (x2, y2) = self.sites[twin_face];
+        let (x, y) = ((x1 + x2) / 2., (y1 + y2) / 2.);
+        let (dx, dy) = (y1 - y2, x2 - x1);
+        let project = |vertex: usize| {
+            let vertex = &self.vertices[vertex];
+            ((vertex.x - x) * dx + (vertex.y - y) * dy) / (dx * dx + dy * dy)
+        };
+        let t_min = if origin == NIL { -f64::INFINITY } else { project(origin) };
+        let t_max = if end == NIL { f64::INFINITY } else { project(end) };
+        Line { x, y, dx, dy, t_min, t_max }
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use crate::dcel::{Dcel, bound, LineSegment, BoundingBox, BoundResult, BoundSide};
-    use crate::math_helpers::{equals_with_epsilon};
+// A straight edge or an arc, as points along it at increasing parameters
+enum Curve {
+    Straight(Line),
+    // Counterclockwise from the start angle
+    Arc { x: f64, y: f64, radius: f64, start: f64, sweep: f64 }
+}
 
-    #[test]
-    fn it_bounds_segments() {
-        fn result_equal(s1: BoundResult, s2: BoundResult) -> bool {
-            if let BoundResult::Intersect { x: x1, y: y1, side: side1 } = s1 {
-                if let BoundResult::Intersect { x: x2, y: y2, side: side2 } = s2 {
-                    return equals_with_epsilon(x1, x2) && equals_with_epsilon(y1, y2) && side1 == side2;
-                }
+impl Curve {
+    fn range(&self) -> (f64, f64) {
+        match self {
+            Curve::Straight(line) => (line.t_min, line.t_max),
+            Curve::Arc { sweep, .. } => (0., *sweep)
+        }
+    }
+
+    fn point_at(&self, parameter: f64) -> (f64, f64) {
+        match self {
+            Curve::Straight(line) => (line.x + parameter * line.dx, line.y + parameter * line.dy),
+            Curve::Arc { x, y, radius, start, .. } => {
+                (x + radius * (start + parameter).cos(), y + radius * (start + parameter).sin())
             }
-            s1 == s2
         }
+    }
 
-        let segment1 = LineSegment { start_x: 0., start_y: 0., end_x: 100., end_y: -200. };
-        let segment2 = LineSegment { start_x: 0., start_y: 0., end_x: 5., end_y: -2. };
-        let segment3 = LineSegment { start_x: 100., start_y: 50., end_x: 0., end_y: 0. };
-        let segment4 = LineSegment { start_x: 100., start_y: 50., end_x: 20., end_y: 20. };
-        let bbox = BoundingBox { min_x: -10., min_y: -10., max_x: 10., max_y: 10. };
+    // Parameters where the curve, extended to a full line or circle, crosses
+    // a circle
+    fn crossings(&self, x: f64, y: f64, radius: f64) -> Vec<f64> {
+        match self {
+            Curve::Straight(line) => match circle_crossings(line, x, y, radius) {
+                Some((t1, t2)) => vec![t1, t2],
+                None => Vec::new()
+            },
+            Curve::Arc { x: arc_x, y: arc_y, radius: arc_radius, start, .. } => {
+                let distance = (x - arc_x).hypot(y - arc_y);
+                if distance == 0. || distance >= arc_radius + radius || distance <= (arc_radius - radius).abs() {
+                    return Vec::new();
+                }
+                // The crossings are symmetric around the line between the
+                // centers, at this angle from it (law of cosines)
+                let cos = (arc_radius * arc_radius + distance * distance - radius * radius) / (2. * arc_radius * distance);
+                let offset = cos.clamp(-1., 1.).acos();
+                let towards = (y - arc_y).atan2(x - arc_x);
+                [towards - offset, towards + offset].iter()
+                    .map(|angle| (angle - start).rem_euclid(2. * std::f64::consts::PI))
+                    .collect()
+            }
+        }
+    }
+}
 
-        assert!(result_equal(bound(segment1, &bbox), BoundResult::Intersect {
-            x: 5.,
-            y: -10.,
-            side: BoundSide::Bottom
-        }));
+// Writes out a ring as the options say. Outer rings come in counterclockwise
+// and holes clockwise, so both are reversed for clockwise winding.
+fn format_ring(mut ring: Vec<(f64, f64)>, options: &PolygonOptions) -> Vec<(f64, f64)> {
+    if options.remove_collinear {
+        ring = remove_collinear(ring);
+    }
+    if ring.is_empty() {
+        return ring;
+    }
+    if options.winding == Winding::Clockwise {
+        ring.reverse();
+    }
+    if options.start_at_lowest {
+        let lowest = (0..ring.len()).min_by(|a, b| {
+            let (x1, y1) = ring[*a];
+            let (x2, y2) = ring[*b];
+            y1.partial_cmp(&y2).unwrap_or(std::cmp::Ordering::Equal)
+                .then(x1.partial_cmp(&x2).unwrap_or(std::cmp::Ordering::Equal))
+        });
+        ring.rotate_left(lowest.unwrap_or(0));
+    }
+    if options.closed {
+        ring.push(ring[0]);
+    }
+    ring
+}
 
-        assert!(result_equal(bound(segment2, &bbox), BoundResult::Inside));
+// Drops points that are equal to the one before, or exactly in line with their
+// neighbors. Returns an empty ring if it doesn't enclose anything.
+fn remove_collinear(ring: Vec<(f64, f64)>) -> Vec<(f64, f64)> {
+    let is_redundant = |a: (f64, f64), b: (f64, f64), c: (f64, f64)| a == b || b == c || orient2d(a, b, c) == 0.;
+    let mut kept: Vec<(f64, f64)> = Vec::with_capacity(ring.len());
+    for point in ring {
+        kept.push(point);
+        while kept.len() >= 3 && is_redundant(kept[kept.len() - 3], kept[kept.len() - 2], kept[kept.len() - 1]) {
+            kept.remove(kept.len() - 2);
+        }
+    }
+    // The ring wraps around, so its ends can still be redundant
+    let mut start = 0;
+    loop {
+        let len = kept.len() - start;
+        if len < 3 {
+            return Vec::new();
+        }
+        let (first, second) = (kept[start], kept[start + 1]);
+        let (before_last, last) = (kept[kept.len() - 2], kept[kept.len() - 1]);
+        if is_redundant(before_last, last, first) {
+            kept.pop();
+        } else if is_redundant(last, first, second) {
+            start += 1;
+        } else {
+            return kept.split_off(start);
+        }
+    }
+}
 
-        assert!(result_equal(bound(segment3, &bbox), BoundResult::Intersect {
-            x: 10.,
-            y: 5.,
-            side: BoundSide::Right
-        }));
+// How far along the segment from a to b the point nearest to the given point
+// is, as a fraction of its length
+fn distance_along(x: f64, y: f64, (x1, y1): (f64, f64), (x2, y2): (f64, f64)) -> f64 {
+    let (dx, dy) = (x2 - x1, y2 - y1);
+    ((x - x1) * dx + (y - y1) * dy) / (dx * dx + dy * dy)
+}
 
-        assert!(result_equal(bound(segment4, &bbox), BoundResult::Outside));
-    }
+#[cfg(test)]
+mod tests {
+    use crate::clip::BoundingBox;
+    use crate::dcel::{Dcel, NIL};
 
     #[test]
     fn it_bounds_faces() {
         // This test tries to bound a triangle to a bounding box
 
-        let mut dcel = Dcel::new(1);
-        let (edge1, edge1_twin) = dcel.create_twins();
-        let (edge2, edge2_twin) = dcel.create_twins();
-        let (edge3, edge3_twin) = dcel.create_twins();
+        let mut dcel = Dcel::new(vec![(1.5, 0.5)]);
+        let (edge1, edge1_twin) = dcel.create_twins(0, NIL);
+        let (edge2, edge2_twin) = dcel.create_twins(0, NIL);
+        let (edge3, edge3_twin) = dcel.create_twins(0, NIL);
 
         let a = dcel.create_vertex(0., 0.);
-        let b = dcel.create_vertex(2., 1.);
-        let c = dcel.create_vertex(2., 0.);
+        let b = dcel.create_vertex(2., 0.);
+        let c = dcel.create_vertex(2., 1.);
 
         dcel.set_origin(edge1, a);
         dcel.set_origin(edge1_twin, b);
@@ -531,13 +2240,12 @@ mod tests {
 
         dcel.ensure_face(0, 0);
 
-        dcel.bound(&BoundingBox {
-            min_x: -1.,
-            min_y: -1.,
-            max_x: 1.,
-            max_y: 1.
-        });
+        let dcel = dcel.clip(&BoundingBox::new(-1., -1., 1., 1.)).unwrap();
 
-        println!("DCEL: {:?}", dcel.get_polygons());
+        assert_eq!(dcel.get_polygons(), vec![vec![(0., 0.), (1., 0.), (1., 0.5)]]);
+        for (i, halfedge) in dcel.halfedges.iter().enumerate() {
+            assert_eq!(dcel.halfedges[halfedge.next].prev, i);
+            assert_eq!(dcel.halfedges[halfedge.next].face, halfedge.face);
+        }
     }
 }
@@ -0,0 +1,123 @@
+// Densities to weigh the cells by when relaxing them, so that sites gather
+// where the density is high. Any closure from a point to its density will do,
+// or a grid of samples, like the pixels of an image.
+use crate::error::{Result, VoronoiError};
+
+pub trait Density {
+    fn density(&self, x: f64, y: f64) -> f64;
+
+    // The size of the smallest details of the density, which cells are split
+    // into pieces of to integrate it. None if it's smooth, so that a few
+    // pieces per cell will do.
+    fn resolution(&self) -> Option<f64> {
+        None
+    }
+}
+
+impl<F: Fn(f64, f64) -> f64> Density for F {
+    fn density(&self, x: f64, y: f64) -> f64 {
+        self(x, y)
+    }
+}
+
+// Samples over a box, by rows from the bottom, each at the middle of its square
+// of the box (like the pixels of an image). Between them the density is
+// interpolated bilinearly, and past the outer ones it stays the same.
+#[derive(Clone, Debug)]
+pub struct DensityGrid {
+    values: Vec<f64>,
+    columns: usize,
+    rows: usize,
+    min_x: f64,
+    min_y: f64,
+    width: f64,
+    height: f64
+}
+
+impl DensityGrid {
+    pub fn new(values: Vec<f64>, columns: usize, min_x: f64, min_y: f64, max_x: f64, max_y: f64) -> Result<DensityGrid> {
+        if columns == 0 || values.is_empty() || !values.len().is_multiple_of(columns) {
+            return Err(VoronoiError::DegenerateConfiguration("density grid must have whole rows of samples"));
+        }
+        if values.iter().any(|value| !value.is_finite() || *value < 0.) {
+            return Err(VoronoiError::DegenerateConfiguration("densities must be finite and not negative"));
+        }
+        let (width, height) = (max_x - min_x, max_y - min_y);
+        if !(width > 0. && height > 0. && width.is_finite() && height.is_finite()) {
+            return Err(VoronoiError::DegenerateConfiguration("density grid must have a finite, positive area"));
+        }
+        let rows = values.len() / columns;
+        Ok(DensityGrid { values, columns, rows, min_x, min_y, width, height })
+    }
+
+    fn value(&self, column: usize, row: usize) -> f64 {
+        self.values[row * self.columns + column]
+    }
+}
+
+impl Density for DensityGrid {
+    fn density(&self, x: f64, y: f64) -> f64 {
+        // Where the point is in samples, and which four are around it
+        let position = |value: f64, min: f64, size: f64, count: usize| {
+            let position = ((value - min) / size * count as f64 - 0.5).clamp(0., (count - 1) as f64);
+            let low = (position.floor() as usize).min(count.saturating_sub(2));
+            (low, (low + 1).min(count - 1), position - low as f64)
+        };
+        let (left, right, s) = position(x, self.min_x, self.width, self.columns);
+        let (bottom, top, t) = position(y, self.min_y, self.height, self.rows);
+        let below = self.value(left, bottom) * (1. - s) + self.value(right, bottom) * s;
+        let above = self.value(left, top) * (1. - s) + self.value(right, top) * s;
+        below * (1. - t) + above * t
+    }
+
+    fn resolution(&self) -> Option<f64> {
+        Some((self.width / self.columns as f64).min(self.height / self.rows as f64))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{InputSite, BoundingBox};
+    use crate::density::{Density, DensityGrid};
+    use crate::relax::{relax, relax_with_density, Stop};
+    use rand::prelude::*;
+
+    #[test]
+    fn it_relaxes_sites_by_density() {
+        let mut rng = StdRng::seed_from_u64(8);
+        let sites: Vec<(f64, f64)> = (0..40).map(|_| (rng.gen::<f64>(), rng.gen::<f64>())).collect();
+        let input = || sites.iter().map(|&(x, y)| InputSite { x, y }).collect();
+        let region = BoundingBox::new(0., 0., 1., 1.);
+
+        // An even density moves the sites just like the plain relaxation
+        let even = relax_with_density(input(), &region, &|_: f64, _: f64| 2., Stop::Iterations(3)).unwrap();
+        let plain = relax(input(), &region, Stop::Iterations(3)).unwrap();
+        for (a, b) in even.sites.iter().zip(&plain.sites) {
+            assert!((a.x - b.x).abs() < 1e-9 && (a.y - b.y).abs() < 1e-9);
+        }
+        assert!((even.energies[3] - 2. * plain.energies[3]).abs() < 1e-9);
+
+        // A lone site moves straight to the centroid of the density
+        let sites = vec![InputSite { x: 0.1, y: 0.2 }];
+        let ramp = relax_with_density(sites, &region, &|x: f64, _: f64| x, Stop::Iterations(1)).unwrap();
+        assert!((ramp.sites[0].x - 2. / 3.).abs() < 1e-12 && (ramp.sites[0].y - 0.5).abs() < 1e-12);
+
+        // Samples are at the middle of their squares, with the density blended
+        // between them and held past the outer ones
+        let grid = DensityGrid::new(vec![0., 1., 2., 3., 4., 5.], 3, 0., 0., 3., 2.).unwrap();
+        assert_eq!(grid.density(0.5, 0.5), 0.);
+        assert_eq!(grid.density(2.5, 1.5), 5.);
+        assert_eq!(grid.density(1., 1.), 2.);
+        assert_eq!(grid.density(-4., 9.), 3.);
+        assert!(DensityGrid::new(vec![1.; 5], 3, 0., 0., 1., 1.).is_err());
+        assert!(DensityGrid::new(vec![-1.], 1, 0., 0., 1., 1.).is_err());
+        assert!(DensityGrid::new(vec![1.], 1, 0., 0., 0., 1.).is_err());
+
+        // Sites gather where the density is high
+        let grid = DensityGrid::new((0..64 * 64).map(|i| if i % 64 < 32 { 0.05 } else { 1. }).collect(), 64, 0., 0., 1., 1.).unwrap();
+        let stippled = relax_with_density(input(), &region, &grid, Stop::Converged { tolerance: 1e-3, max_iterations: 100 }).unwrap();
+        let right = stippled.sites.iter().filter(|site| site.x > 0.5).count();
+        assert!(right > 25, "{} of 40 sites on the right", right);
+        assert!(stippled.energies.last() < stippled.energies.first());
+    }
+}
@@ -0,0 +1,46 @@
+use std::error::Error;
+use std::fmt;
+
+// Everything that can go wrong while building a diagram. Bad input is reported
+// with the index of the offending site (its position in the input vector) so
+// that callers can point at it; anything else means the sweep ended up in a
+// state it cannot recover from.
+#[derive(Clone, Debug, PartialEq)]
+pub enum VoronoiError {
+    // Two input sites are at the same position
+    DuplicateSites { first: usize, second: usize },
+    // A site has a NaN or infinite coordinate
+    NonFiniteCoordinate { site: usize },
+    // The input can't produce a meaningful diagram (e.g. an empty bounding box)
+    DegenerateConfiguration(&'static str),
+    // One of the sweep's data structures broke an invariant
+    InternalInvariant(String)
+}
+
+impl fmt::Display for VoronoiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VoronoiError::DuplicateSites { first, second } => {
+                write!(f, "sites {} and {} are at the same position", first, second)
+            },
+            VoronoiError::NonFiniteCoordinate { site } => {
+                write!(f, "site {} has a non-finite coordinate", site)
+            },
+            VoronoiError::DegenerateConfiguration(reason) => {
+                write!(f, "degenerate configuration: {}", reason)
+            },
+            VoronoiError::InternalInvariant(context) => {
+                write!(f, "internal invariant violated: {}", context)
+            }
+        }
+    }
+}
+
+impl Error for VoronoiError {}
+
+pub type Result<T> = std::result::Result<T, VoronoiError>;
+
+// Shorthand for bailing out of the sweep with some context
+pub fn invariant<T>(context: String) -> Result<T> {
+    Err(VoronoiError::InternalInvariant(context))
+}
@@ -5,16 +5,35 @@ use slab::Slab;
 use std::cmp::Ordering;
 use std::ops::{Index, IndexMut};
 use crate::Site;
-use crate::math_helpers::equals_with_epsilon;
 use crate::treeprint::print;
 use std::collections::HashMap;
 
 const NULL: usize = !0;
 
+// A vertex event stores the bottom of the circle through its three sites (as
+// x, y and the circle's radius), and where it comes in the sweep
 #[derive(Debug)]
 pub enum Event {
     Site(Site),
-    Vertex(crate::beachline::BeachSegmentHandle, f64, f64, f64)
+    Vertex(crate::beachline::BeachSegmentHandle, f64, f64, f64, EventKey)
+}
+
+// Events are ordered by y, then by x, and then by rank, which puts a vertex
+// event right before (-1) or right after (1) a site at the same position
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EventKey {
+    pub y: f64,
+    pub x: f64,
+    pub rank: i8
+}
+
+impl Event {
+    fn key(&self) -> EventKey {
+        match self {
+            Event::Site(site) => EventKey { y: site.y, x: site.x, rank: 0 },
+            Event::Vertex(_, _, _, _, key) => *key
+        }
+    }
 }
 
 #[derive(Eq, PartialEq, Copy, Clone, Debug, Hash)]
@@ -45,24 +64,11 @@ impl Index<Pointer> for EventQueue {
 
 impl Ord for Event {
     fn cmp(&self, other: &Self) -> Ordering {
-        let (self_x, self_y) = match self {
-            Event::Site(site) => (site.x, site.y),
-            Event::Vertex(_, x, y, _) => (*x, *y)
-        };
-
-        let (other_x, other_y) = match other {
-            Event::Site(site) => (site.x, site.y),
-            Event::Vertex(_, x, y, _) => (*x, *y)
-        };
-
-        if equals_with_epsilon(self_y, other_y) {
-            if equals_with_epsilon(self_x, other_x) {
-                return Ordering::Equal;
-            }
-            if self_x < other_x { Ordering::Less } else { Ordering::Greater }
-        } else {
-            if self_y < other_y { Ordering::Less } else { Ordering::Greater }
-        }
+        // Coordinates are always finite, so this is a total order
+        let (a, b) = (self.key(), other.key());
+        a.y.partial_cmp(&b.y).unwrap_or(Ordering::Equal)
+            .then(a.x.partial_cmp(&b.x).unwrap_or(Ordering::Equal))
+            .then(a.rank.cmp(&b.rank))
     }
 }
 
@@ -98,7 +104,7 @@ impl EventQueue {
     }
 
     pub fn pop(&mut self) -> Option<Event> {
-        if self.heap.len() < 1 {
+        if self.heap.is_empty() {
             return None;
         }
         let ptr = self.heap[0];
@@ -107,10 +113,17 @@ impl EventQueue {
         let event = self.events.remove(ptr.0);
         self.heap_indices_by_events.remove(&ptr);
         self.heap.pop().unwrap();
-        if self.heap.len() > 0 {
+        if !self.heap.is_empty() {
             self.heapify_down(0);
         }
-        return Some(event);
+        Some(event)
+    }
+
+    pub fn peek(&self) -> Option<&Event> {
+        match self.heap.first() {
+            Some(ptr) => Some(&self[*ptr]),
+            None => None
+        }
     }
 
     pub fn len(&self) -> usize {
@@ -119,9 +132,7 @@ impl EventQueue {
 
     pub fn delete(&mut self, handle: EventHandle) -> Option<Event> {
         let ptr = handle.0;
-        let index = self.heap_indices_by_events.get(&ptr);
-        if index.is_none() { return None; }
-        let index = *index.unwrap();
+        let index = *self.heap_indices_by_events.get(&ptr)?;
         let last = self.heap.len() - 1;
         self.swap(index, last);
         let event = self.events.remove(ptr.0);
@@ -134,7 +145,7 @@ impl EventQueue {
             self.heapify_up(index);
             // Heap property is restored
         }
-        return Some(event);
+        Some(event)
     }
 
     fn heapify_up(&mut self, at: usize) {
@@ -199,7 +210,7 @@ impl EventQueue {
     #[allow(dead_code)]
     pub fn print(&self) {
         if self.len() < 1 { return; }
-        print(0 as usize, |i| {
+        print(0_usize, |i| {
             let left = self.left(*i);
             if left == NULL { None } else { Some(left) }
         }, |i| {
@@ -213,14 +224,14 @@ impl EventQueue {
         for i in 0..self.heap.len() {
             print!("{},", self.index_to_string(i));
         }
-        println!("");
+        println!();
     }
 
     fn index_to_string(&self, i: usize) -> String {
         let event = &self[self.heap[i]];
         match *event {
             Event::Site(site) => format!("S:{}", site.y),
-            Event::Vertex(_, _, y, _) => format!("V:{}", y)
+            Event::Vertex(_, _, y, _, _) => format!("V:{}", y)
         }
     }
 
@@ -0,0 +1,352 @@
+// Interpolates values given at the sites. Natural neighbor interpolation
+// inserts the point as a new site and weighs the values of the sites whose
+// cells it takes a piece of: by the area of the piece (Sibson), or by the
+// length of the edge it shares with them over the distance to them (Laplace).
+// The sites whose cells it reaches are the ones around the Delaunay triangles
+// whose circumcircles hold the point, and its cell has a corner at the center
+// of the circle through it and each side around those triangles.
+use crate::clip::signed_area;
+use crate::dcel::Dcel;
+use crate::error::{Result, VoronoiError};
+use crate::locate::PointLocator;
+use crate::math_helpers::find_center;
+use crate::predicates::{orient2d, incircle};
+use crate::SiteId;
+use std::collections::{HashMap, HashSet};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Interpolation {
+    // The value of the nearest site, which is defined everywhere
+    Nearest,
+    // Weighted by the areas taken from each neighbor's cell
+    Sibson,
+    // Sibson's interpolant that also follows the gradients at the sites, so
+    // that it's smooth at them too
+    SibsonC1,
+    // Weighted by the length of the edge shared with each neighbor, over the
+    // distance to it
+    Laplace
+}
+
+pub struct NaturalNeighborInterpolator<'a> {
+    locator: PointLocator<'a>,
+    sites: Vec<(f64, f64)>,
+    triangles: Vec<[SiteId; 3]>,
+    neighbors: Vec<[Option<usize>; 3]>,
+    // The triangles around each site, in compressed sparse rows like
+    // `Adjacency`
+    offsets: Vec<usize>,
+    site_triangles: Vec<usize>,
+    values: Vec<f64>,
+    gradients: Vec<(f64, f64)>,
+    method: Interpolation
+}
+
+// A natural neighbor of a point: the area its cell would lose to the point,
+// and the length of the edge they'd share, relative to the point
+struct Neighbor {
+    site: SiteId,
+    area: f64,
+    length: f64
+}
+
+impl<'a> NaturalNeighborInterpolator<'a> {
+    // Interpolates a value for every site, by site id. Sibson's interpolation
+    // is used unless another is set.
+    pub fn new(dcel: &'a Dcel, values: Vec<f64>) -> Result<NaturalNeighborInterpolator<'a>> {
+        let sites: Vec<(f64, f64)> = dcel.faces().map(|face| dcel.site(face)).collect();
+        if values.len() != sites.len() {
+            return Err(VoronoiError::DegenerateConfiguration("there must be a value for every site"));
+        }
+
+        let triangulation = dcel.get_triangulation();
+        let mut offsets = vec![0; sites.len() + 1];
+        for triangle in &triangulation.triangles {
+            for site in triangle {
+                offsets[site + 1] += 1;
+            }
+        }
+        for site in 0..sites.len() {
+            offsets[site + 1] += offsets[site];
+        }
+        let mut site_triangles = vec![0; offsets[sites.len()]];
+        let mut filled = offsets.clone();
+        for (index, triangle) in triangulation.triangles.iter().enumerate() {
+            for site in triangle {
+                site_triangles[filled[*site]] = index;
+                filled[*site] += 1;
+            }
+        }
+
+        let mut interpolator = NaturalNeighborInterpolator {
+            locator: PointLocator::new(dcel),
+            sites,
+            triangles: triangulation.triangles,
+            neighbors: triangulation.neighbors,
+            offsets,
+            site_triangles,
+            values,
+            gradients: Vec::new(),
+            method: Interpolation::Sibson
+        };
+        interpolator.gradients = interpolator.estimate_gradients();
+        Ok(interpolator)
+    }
+
+    pub fn set_method(&mut self, method: Interpolation) {
+        self.method = method;
+    }
+
+    // Sets the gradient at every site for `Interpolation::SibsonC1`. Without
+    // them, they're estimated by fitting a plane to the values at each site's
+    // Delaunay neighbors.
+    pub fn set_gradients(&mut self, gradients: Vec<(f64, f64)>) -> Result<()> {
+        if gradients.len() != self.sites.len() {
+            return Err(VoronoiError::DegenerateConfiguration("there must be a gradient for every site"));
+        }
+        self.gradients = gradients;
+        Ok(())
+    }
+
+    // Returns the interpolated value at a point. Apart from `Nearest` and at
+    // the sites themselves, this is only defined inside the convex hull of the
+    // sites, and None on or outside of it.
+    pub fn interpolate(&self, x: f64, y: f64) -> Option<f64> {
+        let nearest = self.locator.nearest_site(x, y)?;
+        if self.method == Interpolation::Nearest || self.sites[nearest] == (x, y) {
+            return Some(self.values[nearest]);
+        }
+
+        let neighbors = self.natural_neighbors_of(x, y, nearest)?;
+        let area: f64 = neighbors.iter().map(|neighbor| neighbor.area).sum();
+        let distance = |site: SiteId| (self.sites[site].0 - x).hypot(self.sites[site].1 - y);
+        match self.method {
+            Interpolation::Laplace => {
+                let weights: Vec<f64> = neighbors.iter().map(|neighbor| neighbor.length / distance(neighbor.site)).collect();
+                let total: f64 = weights.iter().sum();
+                Some(neighbors.iter().zip(weights).map(|(neighbor, weight)| weight * self.values[neighbor.site]).sum::<f64>() / total)
+            },
+            Interpolation::SibsonC1 => {
+                // Blends the Sibson interpolant with one that follows the
+                // gradients, leaning on the gradients near the sites
+                let (mut linear, mut along_gradients) = (0., 0.);
+                let (mut inverse, mut squared, mut plain) = (0., 0., 0.);
+                for neighbor in &neighbors {
+                    let weight = neighbor.area / area;
+                    let (site_x, site_y) = self.sites[neighbor.site];
+                    let (gradient_x, gradient_y) = self.gradients[neighbor.site];
+                    let distance = distance(neighbor.site);
+                    let value = self.values[neighbor.site];
+                    linear += weight * value;
+                    along_gradients += weight / distance * (value + gradient_x * (x - site_x) + gradient_y * (y - site_y));
+                    inverse += weight / distance;
+                    squared += weight * distance * distance;
+                    plain += weight * distance;
+                }
+                let alpha = plain / inverse;
+                Some((alpha * linear + squared * along_gradients / inverse) / (alpha + squared))
+            },
+            _ => Some(neighbors.iter().map(|neighbor| neighbor.area / area * self.values[neighbor.site]).sum())
+        }
+    }
+
+    // Returns the Sibson coordinates of a point: each natural neighbor, with
+    // the share of the point's cell that would come out of its cell. They add
+    // up to one, and weigh the neighbors' positions to the point itself.
+    pub fn natural_neighbors(&self, x: f64, y: f64) -> Option<Vec<(SiteId, f64)>> {
+        let nearest = self.locator.nearest_site(x, y)?;
+        if self.sites[nearest] == (x, y) {
+            return Some(vec![(nearest, 1.)]);
+        }
+        let neighbors = self.natural_neighbors_of(x, y, nearest)?;
+        let area: f64 = neighbors.iter().map(|neighbor| neighbor.area).sum();
+        Some(neighbors.into_iter().map(|neighbor| (neighbor.site, neighbor.area / area)).collect())
+    }
+
+    fn natural_neighbors_of(&self, x: f64, y: f64, nearest: SiteId) -> Option<Vec<Neighbor>> {
+        // The triangles whose circumcircles hold the point. They're all
+        // connected, and one of them is around the nearest site.
+        let point = (x, y);
+        let holds_point = |triangle: usize| {
+            let [a, b, c] = self.triangles[triangle];
+            incircle(self.sites[a], self.sites[b], self.sites[c], point) > 0.
+        };
+        let mut cavity: HashSet<usize> = HashSet::new();
+        let mut stack: Vec<usize> = self.site_triangles[self.offsets[nearest]..self.offsets[nearest + 1]].iter()
+            .copied()
+            .filter(|triangle| holds_point(*triangle))
+            .collect();
+        while let Some(triangle) = stack.pop() {
+            if !cavity.insert(triangle) {
+                continue;
+            }
+            for neighbor in self.neighbors[triangle].iter().flatten() {
+                if !cavity.contains(neighbor) && holds_point(*neighbor) {
+                    stack.push(*neighbor);
+                }
+            }
+        }
+
+        // The sides around them, which the point has to see from the inside,
+        // or else it's on or outside the convex hull
+        let mut sides = HashMap::new();
+        for triangle in &cavity {
+            for i in 0..3 {
+                let is_inside = self.neighbors[*triangle][i].is_some_and(|neighbor| cavity.contains(&neighbor));
+                if !is_inside {
+                    let (a, b) = (self.triangles[*triangle][i], self.triangles[*triangle][(i + 1) % 3]);
+                    if orient2d(self.sites[a], self.sites[b], point) <= 0. {
+                        return None;
+                    }
+                    sides.insert(a, b);
+                }
+            }
+        }
+        let first = *sides.keys().next()?;
+        let mut ring = vec![first];
+        let mut site = sides[&first];
+        while site != first {
+            if ring.len() > sides.len() {
+                return None;
+            }
+            ring.push(site);
+            site = *sides.get(&site)?;
+        }
+        if ring.len() != sides.len() {
+            return None;
+        }
+
+        // The corners of the point's cell, relative to it. Corner i is between
+        // sites i and i + 1 of the ring.
+        let relative = |site: SiteId| (self.sites[site].0 - x, self.sites[site].1 - y);
+        let mut corners = Vec::with_capacity(ring.len());
+        for i in 0..ring.len() {
+            let (ax, ay) = relative(ring[i]);
+            let (bx, by) = relative(ring[(i + 1) % ring.len()]);
+            let (cx, cy, _) = find_center(0., 0., ax, ay, bx, by)?;
+            corners.push((cx, cy));
+        }
+
+        // The piece taken from each neighbor is the part of the point's cell
+        // that's nearer to it than to the others
+        let mut neighbors = Vec::with_capacity(ring.len());
+        for (i, site) in ring.iter().enumerate() {
+            let (ax, ay) = relative(*site);
+            let mut piece = corners.clone();
+            for other in &ring {
+                if other != site {
+                    let (bx, by) = relative(*other);
+                    let middle = ((ax + bx) / 2., (ay + by) / 2.);
+                    piece = clip_to_half_plane(&piece, middle, (bx - ax, by - ay));
+                }
+            }
+            let previous = corners[(i + ring.len() - 1) % ring.len()];
+            let length = (corners[i].0 - previous.0).hypot(corners[i].1 - previous.1);
+            neighbors.push(Neighbor { site: *site, area: signed_area(&piece) / 2., length });
+        }
+        Some(neighbors)
+    }
+
+    // Fits a plane through the values at each site's Delaunay neighbors by
+    // least squares, favoring the nearer ones
+    fn estimate_gradients(&self) -> Vec<(f64, f64)> {
+        (0..self.sites.len()).map(|site| {
+            let (x, y) = self.sites[site];
+            let (mut xx, mut xy, mut yy, mut xv, mut yv) = (0., 0., 0., 0., 0.);
+            let mut seen = HashSet::new();
+            for triangle in &self.site_triangles[self.offsets[site]..self.offsets[site + 1]] {
+                for neighbor in self.triangles[*triangle] {
+                    if neighbor == site || !seen.insert(neighbor) {
+                        continue;
+                    }
+                    let (dx, dy) = (self.sites[neighbor].0 - x, self.sites[neighbor].1 - y);
+                    let dv = self.values[neighbor] - self.values[site];
+                    let weight = 1. / (dx * dx + dy * dy);
+                    xx += weight * dx * dx;
+                    xy += weight * dx * dy;
+                    yy += weight * dy * dy;
+                    xv += weight * dx * dv;
+                    yv += weight * dy * dv;
+                }
+            }
+            let determinant = xx * yy - xy * xy;
+            if determinant <= 1e-12 * xx * yy {
+                return (0., 0.);
+            }
+            ((yy * xv - xy * yv) / determinant, (xx * yv - xy * xv) / determinant)
+        }).collect()
+    }
+}
+
+// Keeps the part of a convex polygon on the side of a line through a point
+// that a direction points away from
+fn clip_to_half_plane(polygon: &[(f64, f64)], (x, y): (f64, f64), (dx, dy): (f64, f64)) -> Vec<(f64, f64)> {
+    let side = |(px, py): (f64, f64)| (px - x) * dx + (py - y) * dy;
+    let mut clipped = Vec::with_capacity(polygon.len() + 1);
+    for i in 0..polygon.len() {
+        let (a, b) = (polygon[i], polygon[(i + 1) % polygon.len()]);
+        let (side_a, side_b) = (side(a), side(b));
+        if side_a <= 0. {
+            clipped.push(a);
+        }
+        if (side_a < 0. && side_b > 0.) || (side_a > 0. && side_b < 0.) {
+            let t = side_a / (side_a - side_b);
+            clipped.push((a.0 + t * (b.0 - a.0), a.1 + t * (b.1 - a.1)));
+        }
+    }
+    clipped
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Voronoi, InputSite};
+    use crate::interpolate::{NaturalNeighborInterpolator, Interpolation};
+    use rand::prelude::*;
+
+    #[test]
+    fn it_interpolates_between_sites() {
+        let mut rng = StdRng::seed_from_u64(4);
+        let sites: Vec<(f64, f64)> = (0..100).map(|_| (rng.gen::<f64>() * 10., rng.gen::<f64>() * 10.)).collect();
+        let input = sites.iter().map(|&(x, y)| InputSite { x, y }).collect();
+        let diagram = Voronoi::try_build(input, 0., 0., 10., 10.).unwrap();
+
+        // Every method but the nearest site's value follows a plane exactly
+        let plane = |x: f64, y: f64| 2. * x - 3. * y + 1.;
+        let mut interpolator = NaturalNeighborInterpolator::new(&diagram, sites.iter().map(|&(x, y)| plane(x, y)).collect()).unwrap();
+        for method in [Interpolation::Sibson, Interpolation::SibsonC1, Interpolation::Laplace] {
+            interpolator.set_method(method);
+            for _ in 0..200 {
+                let (x, y) = (rng.gen::<f64>() * 6. + 2., rng.gen::<f64>() * 6. + 2.);
+                let value = interpolator.interpolate(x, y).unwrap();
+                assert!((value - plane(x, y)).abs() < 1e-9, "{:?} {} != {}", method, value, plane(x, y));
+            }
+            assert_eq!(interpolator.interpolate(-1., 5.), None);
+            assert_eq!(interpolator.interpolate(sites[7].0, sites[7].1), Some(plane(sites[7].0, sites[7].1)));
+        }
+        interpolator.set_method(Interpolation::Nearest);
+        let nearest = sites.iter().min_by(|a, b| (a.0 + 1.).hypot(a.1 - 5.).partial_cmp(&(b.0 + 1.).hypot(b.1 - 5.)).unwrap()).unwrap();
+        assert_eq!(interpolator.interpolate(-1., 5.), Some(plane(nearest.0, nearest.1)));
+
+        // The coordinates add up to one and weigh the sites to the point
+        let coordinates = interpolator.natural_neighbors(4.5, 5.5).unwrap();
+        let total: f64 = coordinates.iter().map(|(_, weight)| weight).sum();
+        let x: f64 = coordinates.iter().map(|(site, weight)| weight * sites[*site].0).sum();
+        let y: f64 = coordinates.iter().map(|(site, weight)| weight * sites[*site].1).sum();
+        assert!((total - 1.).abs() < 1e-12 && (x - 4.5).abs() < 1e-9 && (y - 5.5).abs() < 1e-9);
+        assert!(coordinates.iter().all(|(_, weight)| *weight > 0.));
+
+        // With the right gradients, Sibson's smooth interpolant follows a
+        // paraboloid too
+        let bowl = |x: f64, y: f64| x * x + y * y - x;
+        let mut interpolator = NaturalNeighborInterpolator::new(&diagram, sites.iter().map(|&(x, y)| bowl(x, y)).collect()).unwrap();
+        interpolator.set_method(Interpolation::SibsonC1);
+        interpolator.set_gradients(sites.iter().map(|&(x, y)| (2. * x - 1., 2. * y)).collect()).unwrap();
+        for _ in 0..200 {
+            let (x, y) = (rng.gen::<f64>() * 6. + 2., rng.gen::<f64>() * 6. + 2.);
+            let value = interpolator.interpolate(x, y).unwrap();
+            assert!((value - bowl(x, y)).abs() < 1e-9, "{} != {}", value, bowl(x, y));
+        }
+        assert!(interpolator.set_gradients(Vec::new()).is_err());
+        assert!(NaturalNeighborInterpolator::new(&diagram, vec![0.; 3]).is_err());
+    }
+}
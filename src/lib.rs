@@ -3,13 +3,31 @@ mod beachline;
 mod eventqueue;
 mod treeprint;
 mod dcel;
+mod clip;
+mod metrics;
+mod locate;
+mod interpolate;
+mod relax;
+mod density;
+mod error;
+mod predicates;
 
 pub use math_helpers::{equals_with_epsilon, breakpoint_between, find_center};
+pub use predicates::{orient2d, incircle};
+use math_helpers::{is_left_of_breakpoint, is_right_of_breakpoint};
 use beachline::{BeachLine, BeachSegmentHandle};
-use eventqueue::{Event, EventQueue, EventHandle};
+use eventqueue::{Event, EventKey, EventQueue, EventHandle};
 use std::cmp::Ordering;
 use std::collections::HashMap;
-use dcel::{Dcel, BoundingBox};
+pub use dcel::{Dcel, FaceId, HalfEdgeId, VertexId};
+pub use metrics::CellMetrics;
+pub use locate::PointLocator;
+pub use interpolate::{NaturalNeighborInterpolator, Interpolation};
+pub use relax::{relax, relax_with_density, Stop, Relaxation};
+pub use density::{Density, DensityGrid};
+pub use clip::{ClipRegion, BoundingBox, ConvexPolygon, PolygonRegion, Circle};
+pub use error::VoronoiError;
+use error::{Result, invariant};
 
 pub struct InputSite {
     pub x: f64,
@@ -25,22 +43,141 @@ pub struct Site {
     pub id: usize
 }
 
-pub struct Diagram {
-    pub edges: Vec<Edge>
+// The diagram before it's clipped to a bounding box: its vertices, every edge
+// along with the two sites it separates, and the cell of every site
+#[derive(Debug)]
+pub struct UnboundedDiagram {
+    pub vertices: Vec<(f64, f64)>,
+    pub edges: Vec<UnboundedEdge>,
+    pub cells: Vec<UnboundedCell>
+}
+
+// A cell that hasn't been clipped. Cells of sites on the convex hull aren't
+// closed, so they're a chain of vertices that a ray comes into and another one
+// leaves. Vertices are counterclockwise around the site.
+#[derive(Clone, Debug, PartialEq)]
+pub enum UnboundedCell {
+    Closed(Vec<(f64, f64)>),
+    // Rays are given by their direction, the incoming one ends at the first
+    // vertex and the outgoing one starts at the last
+    Open { incoming: (f64, f64), vertices: Vec<(f64, f64)>, outgoing: (f64, f64) },
+    // Only if all sites are collinear (or there's just one): the cell is on the
+    // left of every line, given as a point and a direction. With no lines it's
+    // the whole plane.
+    Lines(Vec<(f64, f64, f64, f64)>)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct UnboundedEdge {
+    pub edge: Edge,
+    // Site ids, the first one is on the left when following the edge
+    pub sites: (usize, usize)
+}
+
+// The cell of an input site, which is left out of the diagram if it doesn't
+// overlap the region. Clipping to a region that isn't convex can split a cell
+// into several parts or cut holes into it, and then all of them are listed.
+// Cells of a diagram that hasn't been clipped are unbounded unless other sites
+// close them in.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Cell {
+    Polygon(Vec<(f64, f64)>),
+    Parts(Vec<CellPart>),
+    Outside,
+    Unbounded
+}
+
+// Sites are numbered in input order, unless duplicates have been merged (see
+// `Voronoi::merge_duplicates`). The face of a site has the same index.
+pub type SiteId = usize;
+
+// The edge that a site shares with a neighbor: its total length, which is
+// infinite for rays, and the first halfedge of it around the site
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EdgeInfo {
+    pub length: f64,
+    pub halfedge: HalfEdgeId
+}
+
+// Which sites share an edge, in compressed sparse rows: the neighbors of site
+// i, and the lengths of the edges they share, are at offsets[i]..offsets[i + 1]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Adjacency {
+    pub offsets: Vec<usize>,
+    pub neighbors: Vec<SiteId>,
+    pub lengths: Vec<f64>
+}
+
+// The Delaunay triangulation of the sites, which is the dual of the diagram:
+// the sites around each vertex of the diagram form a triangle, or a polygon
+// that's split into triangles if they're cocircular. Triangles are
+// given by the ids of their sites, counterclockwise, and the neighbor at index
+// i of a triangle is the one across its side from corner i to corner i + 1.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Triangulation {
+    pub triangles: Vec<[usize; 3]>,
+    pub neighbors: Vec<[Option<usize>; 3]>
+}
+
+// How polygons are written out, see `Dcel::get_polygons_with`. The default is
+// how `get_polygons` writes them: counterclockwise, not closed, and starting
+// at any vertex.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PolygonOptions {
+    // The winding of outer rings, holes wind the other way
+    pub winding: Winding,
+    // Repeats the first point at the end of every ring
+    pub closed: bool,
+    // Starts every ring at its lowest point, the leftmost one if several are
+    // as low
+    pub start_at_lowest: bool,
+    // Drops points that are equal to the one before, and points where the ring
+    // goes straight on or turns back. Rings with nothing left become empty.
+    pub remove_collinear: bool
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Winding {
+    CounterClockwise,
+    Clockwise
+}
+
+impl Default for PolygonOptions {
+    fn default() -> PolygonOptions {
+        PolygonOptions { winding: Winding::CounterClockwise, closed: false, start_at_lowest: false, remove_collinear: false }
+    }
+}
+
+// One part of a clipped cell: its outer ring (counterclockwise) and any holes
+// in it (clockwise)
+#[derive(Clone, Debug, PartialEq)]
+pub struct CellPart {
+    pub exterior: Vec<(f64, f64)>,
+    pub holes: Vec<Vec<(f64, f64)>>
+}
+
+// An edge around a clipped cell, which is an arc where the cell was clipped to
+// a circle
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CellEdge {
+    Segment { from: (f64, f64), to: (f64, f64) },
+    // Counterclockwise around the center
+    Arc { from: (f64, f64), to: (f64, f64), center: (f64, f64), radius: f64 }
 }
 
 #[derive(Hash, PartialEq, Eq)]
 struct SitePair(usize, usize);
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Edge {
     Half(f64, f64, f64, f64), // A point and a direction (a ray)
-    Full(f64, f64, f64, f64) // Two points (x1, y1, x2, y2)
+    Full(f64, f64, f64, f64), // Two points (x1, y1, x2, y2)
+    Line(f64, f64, f64, f64) // A point and a direction, extending both ways (only if all sites are collinear)
 }
 
 impl PartialEq for Site {
     fn eq(&self, other: &Self) -> bool {
-        return equals_with_epsilon(self.x, other.x) && equals_with_epsilon(self.y, other.y);
+        equals_with_epsilon(self.x, other.x) && equals_with_epsilon(self.y, other.y)
     }
 }
 
@@ -49,121 +186,292 @@ pub struct Voronoi {
     sites: Vec<Site>,
     beach: BeachLine,
     events_by_beach_segment: HashMap<BeachSegmentHandle, EventHandle>,
-    edges_by_site_pair: HashMap<SitePair, Edge>,
     dcel: Dcel,
     halfedges_by_site_pair: HashMap<SitePair, usize>,
-    bounding_box: BoundingBox
+    // The three sites that created each vertex, and the vertex that each one
+    // was merged into (itself, unless several vertex events were coincident)
+    vertex_sites: Vec<[Site; 3]>,
+    vertex_aliases: Vec<usize>,
+    // The sites in the order of the sweep, and how many of them it's passed
+    site_order: Vec<Site>,
+    swept_sites: usize,
+    // The input index of each site, which differs from its id once duplicates
+    // have been merged, and the site id of each input site
+    input_ids: Vec<usize>,
+    input_faces: Vec<usize>,
+    region: Box<dyn ClipRegion>,
+    // The largest distance from each input site that its cell reaches
+    radii: Option<Vec<f64>>
 }
 
 impl Voronoi {
     pub fn new(sites: Vec<InputSite>, min_x: f64, min_y: f64, max_x: f64, max_y: f64) -> Voronoi {
+        Voronoi::with_region(sites, BoundingBox::new(min_x, min_y, max_x, max_y))
+    }
+
+    // Clips the cells to any region instead of a bounding box
+    pub fn with_region<R: ClipRegion + 'static>(sites: Vec<InputSite>, region: R) -> Voronoi {
         let len = sites.len();
-        let sites = sites.iter().enumerate().map(|(i, s)| Site { x: s.x, y: s.y, id: i }).collect();
+        let sites: Vec<Site> = sites.iter().enumerate().map(|(i, s)| Site { x: s.x, y: s.y, id: i }).collect();
+        let dcel = Dcel::new(sites.iter().map(|site| (site.x, site.y)).collect());
         Voronoi {
             events: EventQueue::new(),
             sites,
             beach: BeachLine::new(),
             events_by_beach_segment: HashMap::new(),
-            edges_by_site_pair: HashMap::new(),
-            dcel: Dcel::new(len + 4),
+            dcel,
             halfedges_by_site_pair: HashMap::new(),
-            bounding_box: BoundingBox::new(min_x, min_y, max_x, max_y)
+            vertex_sites: Vec::new(),
+            vertex_aliases: Vec::new(),
+            site_order: Vec::new(),
+            swept_sites: 0,
+            input_ids: (0..len).collect(),
+            input_faces: (0..len).collect(),
+            region: Box::new(region),
+            radii: None
         }
     }
 
+    // Panics if the diagram can't be built, see `try_build` for a version that
+    // reports the problem instead
     pub fn build(sites: Vec<InputSite>, min_x: f64, min_y: f64, max_x: f64, max_y: f64) -> Dcel {
         let voronoi = Voronoi::new(sites, min_x, min_y, max_x, max_y);
         voronoi.run()
     }
 
-    pub fn run(mut self) -> Dcel {
-        self.add_boundary_sites();
+    pub fn try_build(sites: Vec<InputSite>, min_x: f64, min_y: f64, max_x: f64, max_y: f64) -> Result<Dcel> {
+        let voronoi = Voronoi::new(sites, min_x, min_y, max_x, max_y);
+        voronoi.try_run()
+    }
+
+    pub fn build_unbounded(sites: Vec<InputSite>) -> UnboundedDiagram {
+        let voronoi = Voronoi::new(sites, 0., 0., 0., 0.);
+        voronoi.run_unbounded()
+    }
+
+    pub fn try_build_unbounded(sites: Vec<InputSite>) -> Result<UnboundedDiagram> {
+        let voronoi = Voronoi::new(sites, 0., 0., 0., 0.);
+        voronoi.try_run_unbounded()
+    }
+
+    // Merges sites that are at the same position, so that the diagram can be
+    // built from input with duplicates. Returns the face of every input site,
+    // in input order; the merged sites share a face. Faces are numbered in the
+    // order their first site appears in the input.
+    pub fn merge_duplicates(&mut self) -> Vec<usize> {
+        // Sort by x so that duplicates are close together. Every site that
+        // hasn't been merged yet is compared against the following sites until
+        // x is too far apart, and absorbs the ones at the same position.
+        let len = self.sites.len();
+        let mut representatives: Vec<usize> = (0..len).collect();
+        let mut sorted: Vec<usize> = (0..len).collect();
+        sorted.sort_by(|a, b| self.sites[*a].x.partial_cmp(&self.sites[*b].x).unwrap_or(Ordering::Equal));
+        for (i, site) in sorted.iter().enumerate() {
+            if representatives[*site] != *site {
+                continue;
+            }
+            for other in sorted[i + 1..].iter() {
+                if !equals_with_epsilon(self.sites[*site].x, self.sites[*other].x) {
+                    break;
+                }
+                if representatives[*other] == *other && self.sites[*site] == self.sites[*other] {
+                    representatives[*other] = *site;
+                }
+            }
+        }
+
+        let mut faces = vec![0; len];
+        let mut sites = Vec::new();
+        let mut input_ids = Vec::new();
+        for (i, representative) in representatives.iter().enumerate() {
+            if *representative == i {
+                faces[i] = sites.len();
+                sites.push(Site { x: self.sites[i].x, y: self.sites[i].y, id: sites.len() });
+                input_ids.push(self.input_ids[i]);
+            }
+        }
+        for (i, representative) in representatives.iter().enumerate() {
+            faces[i] = faces[*representative];
+        }
+
+        self.dcel = Dcel::new(sites.iter().map(|site| (site.x, site.y)).collect());
+        self.sites = sites;
+        self.input_ids = input_ids;
+        self.input_faces = self.input_faces.iter().map(|face| faces[*face]).collect();
+        faces
+    }
+
+    // Limits every cell to a disk around its site, with one radius for each
+    // input site. An infinite radius leaves the cell as it is.
+    pub fn set_radii(&mut self, radii: Vec<f64>) {
+        self.radii = Some(radii);
+    }
+
+    pub fn run(self) -> Dcel {
+        match self.try_run() {
+            Ok(dcel) => dcel,
+            Err(error) => panic!("Failed to build voronoi diagram: {}", error)
+        }
+    }
+
+    pub fn try_run(mut self) -> Result<Dcel> {
+        self.validate()?;
+        self.sweep()?;
+        self.dcel.set_input_faces(self.input_faces.clone());
+        let mut dcel = self.dcel.clip(self.region.as_ref())?;
+        if let Some(radii) = &self.radii {
+            let radii: Vec<f64> = self.input_ids.iter().map(|id| radii[*id]).collect();
+            dcel = dcel.bound_to_radii(&radii);
+        }
+        Ok(dcel)
+    }
 
+    // Builds the diagram without clipping it, so that it can be clipped to
+    // several regions with `Dcel::clip`. The region and radii are ignored, and
+    // the cells of sites on the convex hull aren't closed.
+    pub fn run_unclipped(self) -> Dcel {
+        match self.try_run_unclipped() {
+            Ok(dcel) => dcel,
+            Err(error) => panic!("Failed to build voronoi diagram: {}", error)
+        }
+    }
+
+    pub fn try_run_unclipped(mut self) -> Result<Dcel> {
+        self.validate_sites()?;
+        self.sweep()?;
+        self.dcel.set_input_faces(self.input_faces);
+        Ok(self.dcel)
+    }
+
+    // Builds the diagram without clipping it, so the region is ignored
+    pub fn run_unbounded(self) -> UnboundedDiagram {
+        match self.try_run_unbounded() {
+            Ok(diagram) => diagram,
+            Err(error) => panic!("Failed to build voronoi diagram: {}", error)
+        }
+    }
+
+    pub fn try_run_unbounded(mut self) -> Result<UnboundedDiagram> {
+        self.validate_sites()?;
+        self.sweep()?;
+        Ok(self.dcel.get_unbounded())
+    }
+
+    // Runs Fortune's algorithm over all sites, leaving the unbounded diagram in
+    // self.dcel
+    fn sweep(&mut self) -> Result<()> {
         for site in self.sites.iter() {
-            self.events.insert(Event::Site(site.clone()));
+            self.events.insert(Event::Site(*site));
         }
+        self.site_order = self.sites.clone();
+        self.site_order.sort_by(|a, b| (a.y, a.x).partial_cmp(&(b.y, b.x)).unwrap_or(Ordering::Equal));
 
         let first_site = self.events.pop();
-        if let Some(Event::Site(site)) = first_site {
-            self.beach.init(site);
+        let mut last_segment = if let Some(Event::Site(site)) = first_site {
+            self.swept_sites += 1;
+            self.beach.init(site)
         } else {
             // No points
-            return self.dcel;
+            return Ok(());
+        };
+
+        // Sites level with the first one don't have an arc above them yet, and
+        // splitting a degenerate (vertical) arc would put the wrong neighbors
+        // next to each other. They arrive in order of increasing x, so each one
+        // just goes to the right of the last, separated by a vertical bisector.
+        while let Some(Event::Site(site)) = self.events.peek() {
+            let last_site = *self.beach.get(last_segment);
+            if site.y != last_site.y {
+                break;
+            }
+            let site = *site;
+            self.events.pop();
+            self.swept_sites += 1;
+            last_segment = self.beach.insert_after(last_segment, site);
+            self.create_halfedges(last_site, site)?;
         }
 
         while self.events.len() > 0 {
             match self.events.pop() {
                 Some(Event::Site(site)) => {
+                    self.swept_sites += 1;
+
                     // Find beach segment directly above site.x
 
-                    let x = site.x;
-                    let y = site.y;
+                    let point = (site.x, site.y);
                     let segment_to_split = self.beach.search(|ptr| {
                         let site = self.beach.get(ptr);
                         let left_ptr = self.beach.predecessor(ptr);
-                        let left_breakpoint = if left_ptr.is_null() {
-                            -std::f64::MAX
-                        } else {
+                        if !left_ptr.is_null() {
                             let left = self.beach.get(left_ptr);
-                            breakpoint_between(left.x, left.y, site.x, site.y, y)
-                        };
-                        if x < left_breakpoint {
-                            return Ordering::Less;
+                            if is_left_of_breakpoint((left.x, left.y), (site.x, site.y), point) {
+                                return Ordering::Less;
+                            }
                         }
                         let right_ptr = self.beach.successor(ptr);
-                        let right_breakpoint = if right_ptr.is_null() {
-                            std::f64::MAX
-                        } else {
+                        if !right_ptr.is_null() {
                             let right = self.beach.get(right_ptr);
-                            breakpoint_between(site.x, site.y, right.x, right.y, y)
-                        };
-                        if x > right_breakpoint {
-                            return Ordering::Greater;
+                            if is_right_of_breakpoint((site.x, site.y), (right.x, right.y), point) {
+                                return Ordering::Greater;
+                            }
                         }
                         Ordering::Equal
                     });
+                    if segment_to_split.is_null() {
+                        return invariant(format!("no beach segment above site {}", site.id));
+                    }
 
                     self.delete_vertex_event(segment_to_split);
                     let left_segment = segment_to_split;
                     let middle_segment = self.beach.insert_after(segment_to_split, site);
-                    let right_segment = self.beach.insert_after(middle_segment, self.beach.get(segment_to_split).clone());
+                    let right_segment = self.beach.insert_after(middle_segment, *self.beach.get(segment_to_split));
 
                     // Re-create vertex events for split segment
-                    self.create_vertex_event(left_segment);
-                    self.create_vertex_event(right_segment);
+                    self.create_vertex_event(left_segment)?;
+                    self.create_vertex_event(right_segment)?;
 
-                    self.create_halfedges(site, self.beach.get(segment_to_split).clone());
+                    self.create_halfedges(site, *self.beach.get(segment_to_split))?;
                 },
-                Some(Event::Vertex(middle, x, y, rad)) => {
+                Some(Event::Vertex(middle, x, y, rad, _)) => {
                     // We're at this vertex event, make sure we don't reference it again
                     self.events_by_beach_segment.remove(&middle);
 
                     let left = self.beach.predecessor(middle);
                     let right = self.beach.successor(middle);
-                    let middle_site = self.beach.delete(middle).unwrap();
+                    if left.is_null() || right.is_null() {
+                        return invariant(String::from("vertex event for a beach segment without two neighbors"));
+                    }
+                    let middle_site = self.beach.delete(middle)?;
                     self.delete_vertex_event(left);
                     self.delete_vertex_event(right);
-                    self.create_vertex_event(left);
-                    self.create_vertex_event(right);
+                    self.create_vertex_event(left)?;
+                    self.create_vertex_event(right)?;
                     let vertex_x = x;
                     let vertex_y = y-rad;
 
-                    let left_site = self.beach.get(left).clone();
-                    let right_site = self.beach.get(right).clone();
+                    let left_site = *self.beach.get(left);
+                    let right_site = *self.beach.get(right);
 
                     // Add vertex to edges
                     // Get edge id of (left, middle) edge LM and
                     //  set LM origin to V
                     //  set LM's twin's next to MR
-                    let lm = self.get_halfedge(left_site, middle_site);
-                    let mr = self.get_halfedge(middle_site, right_site);
+                    let lm = self.get_halfedge(left_site, middle_site)?;
+                    let mr = self.get_halfedge(middle_site, right_site)?;
 
                     let lm_twin = self.dcel.get_twin(lm);
                     let mr_twin = self.dcel.get_twin(mr);
 
-                    let vertex = self.dcel.create_vertex(vertex_x, vertex_y);
+                    // With four or more cocircular sites, several vertex events
+                    // fire at the same point. Reuse the vertex that the first one
+                    // created so that we end up with a single vertex there.
+                    let sites = [left_site, middle_site, right_site];
+                    let vertex = match self.find_coincident_vertex(&[lm, mr], &sites) {
+                        Some(vertex) => vertex,
+                        None => self.create_vertex(sites, vertex_x, vertex_y)
+                    };
 
-                    let (rl, rl_twin) = self.create_halfedges(right_site, left_site);
+                    let (rl, rl_twin) = self.create_halfedges(right_site, left_site)?;
 
                     self.dcel.set_origin(lm_twin, vertex);
                     self.dcel.set_origin(mr_twin, vertex);
@@ -175,10 +483,6 @@ impl Voronoi {
 
                     // Get edge id of (middle, right) edge MR and set its next to LM
                     // Create new (right, left) edge
-
-                    self.add_vertex_to_edge(left_site, middle_site, vertex_x, vertex_y);
-                    self.add_vertex_to_edge(middle_site, right_site, vertex_x, vertex_y);
-                    self.add_vertex_to_edge(right_site, left_site, vertex_x, vertex_y);
                 }
                 None => {
                     // Impossible
@@ -186,36 +490,121 @@ impl Voronoi {
             }
         }
 
-        let mut edges = Vec::new();
-        for (_, value) in self.edges_by_site_pair.drain() {
-            edges.push(value);
-        }
-
         // println!("{:?}", self.dcel);
         // println!("{:?}", self.dcel.get_polygons());
 
-        self.dcel.bound(&self.bounding_box);
-        self.dcel
+        // Coincident vertices left zero-length edges between them, remove those
+        let aliases: Vec<usize> = (0..self.vertex_aliases.len()).map(|vertex| self.resolve_vertex(vertex)).collect();
+        self.dcel.merge_vertices(&aliases);
+        self.dcel.find_open_chains();
+        self.dcel.find_triangles();
+        Ok(())
     }
 
-    fn create_halfedges(&mut self, left: Site, right: Site) -> (usize, usize) {
-        if let Some(_) = self.halfedges_by_site_pair.get(&SitePair(left.id, right.id)) {
-            panic!(format!("Edge already exists: {} {}", left.id, right.id));
+    // Checks the input for anything the sweep or the clipping can't handle
+    fn validate(&self) -> Result<()> {
+        self.region.validate()?;
+        if let Some(radii) = &self.radii {
+            if self.input_ids.iter().any(|id| *id >= radii.len()) {
+                return Err(VoronoiError::DegenerateConfiguration("there must be a radius for every site"));
+            }
+            if radii.iter().any(|radius| radius.is_nan() || *radius <= 0.) {
+                return Err(VoronoiError::DegenerateConfiguration("radii must be positive"));
+            }
         }
-        let (edge, twin) = self.dcel.create_twins();
+        self.validate_sites()
+    }
+
+    fn validate_sites(&self) -> Result<()> {
+        if let Some(site) = self.sites.iter().find(|site| !site.x.is_finite() || !site.y.is_finite()) {
+            return Err(VoronoiError::NonFiniteCoordinate { site: self.input_ids[site.id] });
+        }
+
+        // Sort by x so that duplicates are close together, then compare each
+        // site against the following sites until x is too far apart
+        let mut sorted: Vec<&Site> = self.sites.iter().collect();
+        sorted.sort_by(|a, b| a.x.partial_cmp(&b.x).unwrap_or(Ordering::Equal));
+        for (i, site) in sorted.iter().enumerate() {
+            for other in sorted[i + 1..].iter() {
+                if !equals_with_epsilon(site.x, other.x) {
+                    break;
+                }
+                if site == other {
+                    let (a, b) = (self.input_ids[site.id], self.input_ids[other.id]);
+                    let (first, second) = if a < b { (a, b) } else { (b, a) };
+                    return Err(VoronoiError::DuplicateSites { first, second });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn create_halfedges(&mut self, left: Site, right: Site) -> Result<(usize, usize)> {
+        if self.halfedges_by_site_pair.contains_key(&SitePair(left.id, right.id)) {
+            return invariant(format!("edge already exists between sites {} and {}", left.id, right.id));
+        }
+        let (edge, twin) = self.dcel.create_twins(left.id, right.id);
         self.halfedges_by_site_pair.insert(SitePair(left.id, right.id), edge);
         self.halfedges_by_site_pair.insert(SitePair(right.id, left.id), twin);
         self.dcel.ensure_face(left.id, edge);
         self.dcel.ensure_face(right.id, twin);
-        (edge, twin)
+        Ok((edge, twin))
     }
 
-    fn get_halfedge(&self, left: Site, right: Site) -> usize {
+    fn get_halfedge(&self, left: Site, right: Site) -> Result<usize> {
         if let Some(halfedge) = self.halfedges_by_site_pair.get(&SitePair(left.id, right.id)) {
-            *halfedge
+            Ok(*halfedge)
         } else {
-            panic!("Tried getting a non-existant halfedge");
+            invariant(format!("no edge between sites {} and {}", left.id, right.id))
+        }
+    }
+
+    fn create_vertex(&mut self, sites: [Site; 3], x: f64, y: f64) -> usize {
+        let vertex = self.dcel.create_vertex(x, y);
+        self.vertex_sites.push(sites);
+        self.vertex_aliases.push(vertex);
+        vertex
+    }
+
+    // Follows merges until reaching the vertex that's actually kept
+    fn resolve_vertex(&self, mut vertex: usize) -> usize {
+        while self.vertex_aliases[vertex] != vertex {
+            vertex = self.vertex_aliases[vertex];
         }
+        vertex
+    }
+
+    // Looks for a vertex at the start of the given halfedges that's at the same
+    // position as a new vertex with the given sites. If the halfedges start at
+    // two different such vertices, they're merged.
+    fn find_coincident_vertex(&mut self, halfedges: &[usize], sites: &[Site; 3]) -> Option<usize> {
+        let mut found = None;
+        for halfedge in halfedges {
+            let origin = self.dcel.get_origin(*halfedge);
+            if origin == !0 {
+                continue;
+            }
+            let origin = self.resolve_vertex(origin);
+            if !self.is_coincident(origin, sites) {
+                continue;
+            }
+            match found {
+                None => found = Some(origin),
+                Some(vertex) if vertex != origin => self.vertex_aliases[origin] = vertex,
+                _ => {}
+            }
+        }
+        found
+    }
+
+    fn is_coincident(&self, vertex: usize, sites: &[Site; 3]) -> bool {
+        // Exactly cocircular sites share a vertex even if its position was
+        // computed slightly differently each time. Sites that are only nearly
+        // cocircular keep their own vertices, however close together.
+        let [a, b, c] = self.vertex_sites[vertex];
+        sites.iter().all(|site| {
+            incircle((a.x, a.y), (b.x, b.y), (c.x, c.y), (site.x, site.y)) == 0.
+        })
     }
 
     fn delete_vertex_event(&mut self, segment: BeachSegmentHandle) {
@@ -225,65 +614,932 @@ impl Voronoi {
         }
     }
 
-    fn create_vertex_event(&mut self, segment: BeachSegmentHandle) {
-        if let Some(_) = self.events_by_beach_segment.get(&segment) {
-            panic!("Creating an already-existing vertex event");
+    // Where a vertex event comes in the sweep. The bottom of its circle is
+    // rounded, so sites that are about as far along can't be ordered against
+    // it by y. Instead, the ones inside the circle come first (they invalidate
+    // the event) and the rest come after it.
+    fn vertex_event_key(&self, sites: &[Site; 3], x: f64, y: f64) -> EventKey {
+        let tolerance = 1e-12 * y.abs().max(1.);
+        let pending = &self.site_order[self.swept_sites..];
+        let start = pending.partition_point(|site| site.y < y - tolerance);
+        let [a, b, c] = sites;
+        let (mut last_inside, mut first_outside) = (None, None);
+        for site in pending[start..].iter().take_while(|site| site.y <= y + tolerance) {
+            if incircle((a.x, a.y), (b.x, b.y), (c.x, c.y), (site.x, site.y)) > 0. {
+                last_inside = Some(site);
+            } else if first_outside.is_none() {
+                first_outside = Some(site);
+            }
+        }
+        // If a site inside comes after one outside, the one inside wins
+        match (last_inside, first_outside) {
+            (Some(site), _) => EventKey { y: site.y, x: site.x, rank: 1 },
+            (None, Some(site)) if (site.y, site.x) < (y, x) => EventKey { y: site.y, x: site.x, rank: -1 },
+            _ => EventKey { y, x, rank: -1 }
+        }
+    }
+
+    fn create_vertex_event(&mut self, segment: BeachSegmentHandle) -> Result<()> {
+        if self.events_by_beach_segment.contains_key(&segment) {
+            return invariant(format!("vertex event already exists for site {}", self.beach.get(segment).id));
         }
 
         let left = self.beach.predecessor(segment);
         let right = self.beach.successor(segment);
         if left.is_null() || right.is_null() {
-            return;
+            return Ok(());
         }
         let left_site = self.beach.get(left);
         let middle_site = self.beach.get(segment);
         let right_site = self.beach.get(right);
 
         // Don't add a vertex event unless these points result in a collapsing
-        // segment (i.e. they are clockwise)
-        let is_clockwise = (middle_site.y - left_site.y) * (right_site.x - middle_site.x) - (right_site.y - middle_site.y) * (middle_site.x - left_site.x) > 0.0;
-        if is_clockwise { return; }
+        // segment (i.e. they are counterclockwise). This is decided exactly, so
+        // nearly collinear sites never produce a vertex on the wrong side.
+        let is_counterclockwise = orient2d((left_site.x, left_site.y), (middle_site.x, middle_site.y), (right_site.x, right_site.y)) > 0.;
+        if !is_counterclockwise { return Ok(()); }
 
-        let center = find_center(left_site.x, left_site.y, middle_site.x, middle_site.y, right_site.x, right_site.y);
-        if center.is_none() { return; }
-        let (center_x, center_y, rad) = center.unwrap();
+        // Dropping the event would leave the beach line in the wrong order, so
+        // sites whose circle is too big to represent can't be handled
+        let (center_x, center_y, rad) = match find_center(left_site.x, left_site.y, middle_site.x, middle_site.y, right_site.x, right_site.y) {
+            Some(center) => center,
+            None => return Err(VoronoiError::DegenerateConfiguration("sites are too close to collinear for the circle through them to be represented"))
+        };
 
-        let event_handle = self.events.insert(Event::Vertex(segment, center_x, center_y + rad, rad));
+        let sites = [*left_site, *middle_site, *right_site];
+        let key = self.vertex_event_key(&sites, center_x, center_y + rad);
+        let event_handle = self.events.insert(Event::Vertex(segment, center_x, center_y + rad, rad, key));
         self.events_by_beach_segment.insert(segment, event_handle);
+        Ok(())
     }
+}
 
-    fn add_vertex_to_edge(&mut self, a: Site, b: Site, x: f64, y: f64) {
-        let new_edge = match self.edges_by_site_pair.remove(&SitePair(a.id, b.id)) {
-            Some(Edge::Half(other_x, other_y, _, _)) => {
-                Edge::Full(other_x, other_y, x, y)
-            },
-            Some(edge) => {
-                // Impossible
-                edge
-            },
-            None => {
-                let dx = b.y - a.y;
-                let dy = a.x - b.x;
-                Edge::Half(x, y, dx, dy)
+#[cfg(test)]
+mod tests {
+    use crate::{Voronoi, InputSite, VoronoiError, Edge, orient2d, Cell, CellEdge, UnboundedCell, PolygonOptions, Winding, ClipRegion, BoundingBox, ConvexPolygon, PolygonRegion, Circle};
+    use crate::clip::Boundary;
+    use rand::prelude::*;
+
+    #[test]
+    fn it_rejects_bad_input() {
+        let duplicates = vec![
+            InputSite { x: 1., y: 1. },
+            InputSite { x: 2., y: 3. },
+            InputSite { x: 1., y: 1. }
+        ];
+        assert_eq!(Voronoi::try_build(duplicates, 0., 0., 4., 4.).err(), Some(VoronoiError::DuplicateSites { first: 0, second: 2 }));
+
+        let non_finite = vec![InputSite { x: 1., y: 1. }, InputSite { x: f64::NAN, y: 1. }];
+        assert_eq!(Voronoi::try_build(non_finite, 0., 0., 4., 4.).err(), Some(VoronoiError::NonFiniteCoordinate { site: 1 }));
+
+        let sites = vec![InputSite { x: 1., y: 1. }];
+        match Voronoi::try_build(sites, 0., 0., 0., 4.) {
+            Err(VoronoiError::DegenerateConfiguration(_)) => {},
+            _ => panic!("Expected a degenerate bounding box to be rejected")
+        }
+    }
+
+    #[test]
+    fn it_merges_duplicate_sites() {
+        let sites = vec![
+            InputSite { x: 1., y: 1. },
+            InputSite { x: 3., y: 1. },
+            InputSite { x: 1., y: 1. },
+            InputSite { x: 2., y: 3. },
+            InputSite { x: 3., y: 1. + 1e-14 },
+            InputSite { x: f64::NAN, y: 1. }
+        ];
+        let mut voronoi = Voronoi::new(sites, 0., 0., 4., 4.);
+        assert_eq!(voronoi.merge_duplicates(), vec![0, 1, 0, 2, 1, 3]);
+        // Errors still refer to the input
+        assert_eq!(voronoi.try_run().err(), Some(VoronoiError::NonFiniteCoordinate { site: 5 }));
+
+        let sites = vec![
+            InputSite { x: 1., y: 1. },
+            InputSite { x: 3., y: 1. },
+            InputSite { x: 1., y: 1. },
+            InputSite { x: 2., y: 3. }
+        ];
+        let mut voronoi = Voronoi::new(sites, 0., 0., 4., 4.);
+        assert_eq!(voronoi.merge_duplicates(), vec![0, 1, 0, 2]);
+        let merged = voronoi.try_run().unwrap();
+
+        let unique = vec![
+            InputSite { x: 1., y: 1. },
+            InputSite { x: 3., y: 1. },
+            InputSite { x: 2., y: 3. }
+        ];
+        let expected = Voronoi::try_build(unique, 0., 0., 4., 4.).unwrap();
+        assert_eq!(merged.get_polygons()[..3], expected.get_polygons()[..3]);
+    }
+
+    #[test]
+    fn it_never_panics() {
+        let mut rng = StdRng::seed_from_u64(1);
+        for i in 0..200 {
+            let count = rng.gen_range(0, 40);
+            let mut sites: Vec<InputSite> = (0..count).map(|_| {
+                match i % 4 {
+                    // Points on a small integer grid, lots of cocircular and collinear sites
+                    0 => InputSite { x: rng.gen_range(0, 8) as f64, y: rng.gen_range(0, 8) as f64 },
+                    // Points on a single line
+                    1 => InputSite { x: rng.gen_range(0, 100) as f64, y: 2. },
+                    // Huge coordinates
+                    2 => InputSite { x: rng.gen::<f64>() * 1e300, y: rng.gen::<f64>() * -1e300 },
+                    _ => InputSite { x: rng.gen(), y: rng.gen() }
+                }
+            }).collect();
+            if i % 8 < 4 {
+                // Most of the time, only keep distinct sites so that the sweep actually runs
+                sites.sort_by(|a, b| (a.x, a.y).partial_cmp(&(b.x, b.y)).unwrap());
+                sites.dedup_by(|a, b| a.x == b.x && a.y == b.y);
             }
+            let _ = Voronoi::try_build(sites, 0., 0., 8., 8.);
+        }
+    }
+
+    #[test]
+    fn it_decides_the_sweep_exactly() {
+        // Sites a single ulp above or below each other aren't level, and the
+        // regular polygons have sites that are cocircular only up to rounding.
+        // Either way the cells have to tile the box.
+        let up = |value: f64| f64::from_bits(value.to_bits() + 1);
+        let mut layouts = vec![
+            (0..8).map(|i| (i as f64, if i % 2 == 0 { 1. } else { up(1.) })).chain(vec![(3.5, 4.)]).collect::<Vec<_>>(),
+            (0..8).map(|i| (if i % 3 == 0 { up(i as f64) } else { i as f64 }, 1.)).chain(vec![(3.5, up(1.))]).collect()
+        ];
+        for count in [5, 6, 8, 12].iter() {
+            layouts.push((0..*count).map(|i| {
+                let angle = i as f64 * 2. * std::f64::consts::PI / *count as f64;
+                (4. + 3. * angle.cos(), 4. + 3. * angle.sin())
+            }).collect());
+        }
+        for sites in layouts {
+            let input = sites.iter().map(|&(x, y)| InputSite { x, y }).collect();
+            let diagram = Voronoi::try_build(input, -1., -1., 9., 9.).unwrap();
+            let area: f64 = diagram.get_cell_metrics().iter().map(|cell| cell.unwrap().area).sum();
+            assert!((area - 100.).abs() < 1e-9, "{} for {:?}", area, sites);
+            for [a, b, c] in diagram.get_triangulation().triangles {
+                assert!(orient2d(sites[a], sites[b], sites[c]) > 0.);
+            }
+        }
+
+        // The circle through nearly collinear sites can be too big to represent
+        let sites = vec![InputSite { x: -1e300, y: 1. }, InputSite { x: 0., y: 0. }, InputSite { x: 1e300, y: 1. }];
+        assert!(matches!(Voronoi::try_build_unbounded(sites), Err(VoronoiError::DegenerateConfiguration(_))));
+    }
+
+    #[test]
+    fn it_is_translation_invariant() {
+        // Sheared grid with lots of nearly collinear and cocircular sites. All
+        // coordinates are exactly representable at every offset, so the diagram
+        // should only move with the offset, not change shape.
+        fn polygons_at(offset: f64) -> Vec<Vec<(f64, f64)>> {
+            let mut sites = Vec::new();
+            for i in 0..8 {
+                for j in 0..4 {
+                    let x = 1. + i as f64 + if (i + j) % 3 == 0 { 0.5 } else { 0. };
+                    let y = 1. + 2. * j as f64 + i as f64 * 0.125;
+                    sites.push(InputSite { x: offset + x, y: offset + y });
+                }
+            }
+            let dcel = Voronoi::try_build(sites, offset, offset, offset + 10., offset + 10.).unwrap();
+            dcel.get_polygons().iter().take(32).map(|polygon| {
+                polygon.iter().map(|&(x, y)| (x - offset, y - offset)).collect()
+            }).collect()
+        }
+
+        // Polygons may start at a different vertex, so compare them as cycles
+        fn same_polygon(a: &[(f64, f64)], b: &[(f64, f64)]) -> bool {
+            a.len() == b.len() && (0..a.len().max(1)).any(|rotation| {
+                a.iter().enumerate().all(|(i, p)| {
+                    let q = b[(i + rotation) % b.len()];
+                    (p.0 - q.0).abs() < 1e-3 && (p.1 - q.1).abs() < 1e-3
+                })
+            })
+        }
+
+        let expected = polygons_at(0.);
+        for offset in [1048576., 1099511627776.].iter() {
+            for (a, b) in expected.iter().zip(polygons_at(*offset).iter()) {
+                assert!(same_polygon(a, b), "{:?} != {:?} at offset {}", a, b, offset);
+            }
+        }
+    }
+
+    #[test]
+    fn it_clips_cells_to_the_box() {
+        // Every cell should end up inside the box, closer to its own site than
+        // to any other, and together the cells should cover the whole box. The
+        // box is long and thin so that hull cells are clipped on all sides.
+        let mut rng = StdRng::seed_from_u64(2);
+        let mut configurations: Vec<Vec<(f64, f64)>> = [1, 2, 3, 10, 100].iter().map(|count| {
+            (0..*count).map(|_| (rng.gen::<f64>() * 100., rng.gen::<f64>() * 2.)).collect()
+        }).collect();
+        // Collinear sites only have unbounded edges
+        configurations.push((0..10).map(|i| (i as f64 * 10. + 5., 1.)).collect());
+        configurations.push((0..8).map(|i| (i as f64 * 10. + 5., i as f64 * 0.25)).collect());
+        // Nearly collinear sites have vertices very far away
+        configurations.push((0..10).map(|i| (i as f64 * 10. + 5., i as f64 * 0.2)).collect());
+        for sites in configurations.iter() {
+            let input = sites.iter().map(|&(x, y)| InputSite { x, y }).collect();
+            let polygons = Voronoi::try_build(input, 0., 0., 100., 2.).unwrap().get_polygons();
+            assert_eq!(polygons.len(), sites.len());
+
+            let mut total_area = 0.;
+            for (polygon, (site_x, site_y)) in polygons.iter().zip(sites.iter()) {
+                assert!(polygon.len() >= 3, "Missing cell for site {:?}", (site_x, site_y));
+                let mut area = 0.;
+                for (i, &(x1, y1)) in polygon.iter().enumerate() {
+                    let (x2, y2) = polygon[(i + 1) % polygon.len()];
+                    area += (x1 * y2 - x2 * y1) / 2.;
+
+                    assert!((0. ..=100.).contains(&x1) && (0. ..=2.).contains(&y1));
+                    let distance = (x1 - site_x).hypot(y1 - site_y);
+                    let nearest = sites.iter().map(|(x, y)| (x1 - x).hypot(y1 - y)).fold(f64::INFINITY, f64::min);
+                    assert!(distance <= nearest + 1e-9);
+                }
+                assert!(area > 0.);
+                total_area += area;
+            }
+            assert!((total_area - 200.).abs() < 1e-9);
+        }
+    }
+
+    // Polygons may start at any vertex
+    fn rotated(polygon: &[(f64, f64)]) -> Vec<(f64, f64)> {
+        let start = (0..polygon.len()).min_by(|a, b| polygon[*a].partial_cmp(&polygon[*b]).unwrap()).unwrap_or(0);
+        polygon[start..].iter().chain(polygon[..start].iter()).cloned().collect()
+    }
+
+    #[test]
+    fn it_clips_border_cases() {
+        // The edge between the first two sites crosses the box with both of its
+        // vertices outside, and no vertex is inside the box at all
+        let sites = vec![
+            InputSite { x: 5., y: -3. },
+            InputSite { x: 5., y: 13. },
+            InputSite { x: -20., y: 5. },
+            InputSite { x: 30., y: 5. }
+        ];
+        let polygons = Voronoi::try_build(sites, 0., 0., 10., 10.).unwrap().get_polygons();
+        assert_eq!(rotated(&polygons[0]), vec![(0., 0.), (10., 0.), (10., 5.), (0., 5.)]);
+        assert_eq!(rotated(&polygons[1]), vec![(0., 5.), (10., 5.), (10., 10.), (0., 10.)]);
+        assert!(polygons[2].is_empty() && polygons[3].is_empty());
+
+        // The first site's cell contains the whole box
+        let sites = vec![
+            InputSite { x: 5., y: 5. },
+            InputSite { x: 100., y: 100. },
+            InputSite { x: -100., y: 50. }
+        ];
+        let polygons = Voronoi::try_build(sites, 0., 0., 10., 10.).unwrap().get_polygons();
+        assert_eq!(rotated(&polygons[0]), vec![(0., 0.), (10., 0.), (10., 10.), (0., 10.)]);
+        assert!(polygons[1].is_empty() && polygons[2].is_empty());
+
+        // Vertices lie exactly on the border and at the corners, and edges run
+        // along the border. Only the four middle sites have cells with an area.
+        let mut sites = Vec::new();
+        for i in 0..4 {
+            for j in 0..4 {
+                sites.push(InputSite { x: i as f64, y: j as f64 });
+            }
+        }
+        let polygons = Voronoi::try_build(sites, 0.5, 0.5, 2.5, 2.5).unwrap().get_polygons();
+        for i in 0..4 {
+            for j in 0..4 {
+                let polygon = rotated(&polygons[i * 4 + j]);
+                if i == 0 || i == 3 || j == 0 || j == 3 {
+                    assert!(polygon.is_empty(), "Expected no cell for {:?}, got {:?}", (i, j), polygon);
+                } else {
+                    let (x, y) = (i as f64, j as f64);
+                    assert_eq!(polygon, vec![(x - 0.5, y - 0.5), (x + 0.5, y - 0.5), (x + 0.5, y + 0.5), (x - 0.5, y + 0.5)]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn it_clips_cells_to_a_convex_polygon() {
+        // A hexagon, so cells leave through one side and come back through
+        // another, possibly passing several corners in between
+        let hexagon: Vec<(f64, f64)> = (0..6).map(|i| {
+            let angle = i as f64 * std::f64::consts::PI / 3.;
+            (5. + 4. * angle.cos(), 5. + 4. * angle.sin())
+        }).collect();
+        let hexagon_area = 1.5 * 3f64.sqrt() * 16.;
+
+        let mut rng = StdRng::seed_from_u64(3);
+        for count in [1, 2, 3, 10, 50].iter() {
+            let sites: Vec<(f64, f64)> = (0..*count).map(|_| (rng.gen::<f64>() * 10., rng.gen::<f64>() * 10.)).collect();
+            let input = sites.iter().map(|&(x, y)| InputSite { x, y }).collect();
+            let region = ConvexPolygon::new(hexagon.clone());
+            let polygons = Voronoi::with_region(input, region).try_run().unwrap().get_polygons();
+
+            let region = ConvexPolygon::new(hexagon.clone());
+            let mut total_area = 0.;
+            for (polygon, (site_x, site_y)) in polygons.iter().zip(sites.iter()) {
+                for (i, &(x1, y1)) in polygon.iter().enumerate() {
+                    let (x2, y2) = polygon[(i + 1) % polygon.len()];
+                    total_area += (x1 * y2 - x2 * y1) / 2.;
+
+                    let (clamped_x, clamped_y) = region.clamp(x1, y1);
+                    assert!((clamped_x - x1).abs() < 1e-9 && (clamped_y - y1).abs() < 1e-9);
+                    let distance = (x1 - site_x).hypot(y1 - site_y);
+                    let nearest = sites.iter().map(|(x, y)| (x1 - x).hypot(y1 - y)).fold(f64::INFINITY, f64::min);
+                    assert!(distance <= nearest + 1e-9);
+                }
+            }
+            assert!((total_area - hexagon_area).abs() < 1e-9, "{} != {}", total_area, hexagon_area);
+        }
+
+        let sites = vec![InputSite { x: 1., y: 1. }];
+        let bowtie = ConvexPolygon::new(vec![(0., 0.), (2., 2.), (2., 0.), (0., 2.)]);
+        match Voronoi::with_region(sites, bowtie).try_run() {
+            Err(VoronoiError::DegenerateConfiguration(_)) => {},
+            _ => panic!("Expected a polygon that isn't convex to be rejected")
+        }
+    }
+
+    #[test]
+    fn it_clips_cells_to_polygons_with_holes() {
+        // A U shape with a small lake. The first site is above the gap between
+        // the arms, so its cell is split in two, and the lake is a hole in the
+        // cell of the second site.
+        let exterior = vec![(0., 0.), (6., 0.), (6., 4.), (4., 4.), (4., 1.), (2., 1.), (2., 4.), (0., 4.)];
+        let lake = vec![(0.5, 0.5), (1., 0.5), (1., 1.), (0.5, 1.)];
+        let sites = vec![InputSite { x: 3., y: 4. }, InputSite { x: 3., y: 0. }];
+        let region = PolygonRegion::new(exterior, vec![lake]);
+        let diagram = Voronoi::with_region(sites, region).try_run().unwrap();
+        let cells = diagram.get_multipolygons();
+
+        let mut arms: Vec<Vec<(f64, f64)>> = cells[0].iter().map(|part| rotated(&part.exterior)).collect();
+        arms.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(arms, vec![
+            vec![(0., 2.), (2., 2.), (2., 4.), (0., 4.)],
+            vec![(4., 2.), (6., 2.), (6., 4.), (4., 4.)]
+        ]);
+        assert!(cells[0].iter().all(|part| part.holes.is_empty()));
+
+        assert_eq!(cells[1].len(), 1);
+        assert_eq!(rotated(&cells[1][0].exterior), vec![(0., 0.), (6., 0.), (6., 2.), (4., 2.), (4., 1.), (2., 1.), (2., 2.), (0., 2.)]);
+        assert_eq!(cells[1][0].holes.len(), 1);
+        assert_eq!(rotated(&cells[1][0].holes[0]), vec![(0.5, 0.5), (0.5, 1.), (1., 1.), (1., 0.5)]);
+
+        // Listing cells by input index keeps every part and hole
+        let listed: Vec<Cell> = diagram.cells().map(|(_, cell)| cell).collect();
+        assert_eq!(listed, cells.into_iter().map(Cell::Parts).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn it_clips_cells_to_circles() {
+        use std::f64::consts::PI;
+
+        // The area enclosed by a cell's edges, counting each arc's segment
+        fn area(edges: &[CellEdge]) -> f64 {
+            edges.iter().map(|edge| match *edge {
+                CellEdge::Segment { from, to } => (from.0 * to.1 - to.0 * from.1) / 2.,
+                CellEdge::Arc { from, to, center, radius } => {
+                    let start = (from.1 - center.1).atan2(from.0 - center.0);
+                    let sweep = ((to.1 - center.1).atan2(to.0 - center.0) - start).rem_euclid(2. * PI);
+                    let sweep = if sweep == 0. { 2. * PI } else { sweep };
+                    (from.0 * to.1 - to.0 * from.1) / 2. + radius * radius * (sweep - sweep.sin()) / 2.
+                }
+            }).sum()
+        }
+
+        let mut rng = StdRng::seed_from_u64(11);
+        let sites: Vec<InputSite> = (0..20).map(|_| InputSite { x: rng.gen::<f64>() * 6. - 3., y: rng.gen::<f64>() * 6. - 3. }).collect();
+        let cells = Voronoi::with_region(sites, Circle::new(0., 0., 2.)).try_run().unwrap().get_cell_edges();
+        let total: f64 = cells.iter().map(|edges| area(edges)).sum();
+        assert!((total - 4. * PI).abs() < 1e-9, "{} != {}", total, 4. * PI);
+
+        // A small radius leaves the cell a whole disk
+        let sites = vec![InputSite { x: 0., y: 0. }, InputSite { x: 4., y: 0. }];
+        let mut voronoi = Voronoi::new(sites, -10., -10., 10., 10.);
+        voronoi.set_radii(vec![1., 3.]);
+        let cells = voronoi.try_run().unwrap().get_cell_edges();
+        assert!((area(&cells[0]) - PI).abs() < 1e-9);
+        assert!(cells[0].iter().all(|edge| matches!(edge, CellEdge::Arc { .. })));
+
+        // Two equal disks that overlap share a chord of the bisector
+        let sites = vec![InputSite { x: 0., y: 0. }, InputSite { x: 2., y: 0. }];
+        let mut voronoi = Voronoi::new(sites, -10., -10., 10., 10.);
+        voronoi.set_radii(vec![2., 2.]);
+        let cells = voronoi.try_run().unwrap().get_cell_edges();
+        let chord: Vec<_> = cells[0].iter().filter_map(|edge| match *edge {
+            CellEdge::Segment { from, to } => Some((from, to)),
+            _ => None
+        }).collect();
+        assert_eq!(chord.len(), 1);
+        let ((x1, y1), (x2, y2)) = chord[0];
+        assert!((x1 - 1.).abs() < 1e-12 && (x2 - 1.).abs() < 1e-12);
+        assert!((y1 + 3f64.sqrt()).abs() < 1e-12 && (y2 - 3f64.sqrt()).abs() < 1e-12);
+
+        // Arcs are flattened into more points with a tighter tolerance
+        let sites = vec![InputSite { x: 0., y: 0. }, InputSite { x: 2., y: 0. }];
+        let mut voronoi = Voronoi::new(sites, -10., -10., 10., 10.);
+        voronoi.set_radii(vec![2., 2.]);
+        let mut diagram = voronoi.try_run().unwrap();
+        let coarse = diagram.get_polygons()[0].len();
+        diagram.set_chord_tolerance(1e-6);
+        assert!(diagram.get_polygons()[0].len() > coarse);
+    }
+
+    #[test]
+    fn it_walks_the_diagram() {
+        let mut rng = StdRng::seed_from_u64(12);
+        let sites: Vec<InputSite> = (0..30).map(|_| InputSite { x: rng.gen::<f64>() * 10., y: rng.gen::<f64>() * 10. }).collect();
+        let diagram = Voronoi::new(sites, 0., 0., 10., 10.).try_run().unwrap();
+
+        for halfedge in diagram.halfedges() {
+            assert_eq!(diagram.twin(diagram.twin(halfedge)), halfedge);
+            if let Some(next) = diagram.next(halfedge) {
+                assert_eq!(diagram.origin(next), diagram.origin(diagram.twin(halfedge)));
+                assert_eq!(diagram.prev(next), Some(halfedge));
+                assert_eq!(diagram.face(next), diagram.face(halfedge));
+            }
+            if let Some(prev) = diagram.prev(halfedge) {
+                assert_eq!(diagram.next(prev), Some(halfedge));
+            }
+        }
+
+        let polygons = diagram.get_polygons();
+        for face in diagram.faces() {
+            let points: Vec<(f64, f64)> = diagram.face_halfedges(face)
+                .map(|halfedge| diagram.position(diagram.origin(halfedge).unwrap()))
+                .collect();
+            assert_eq!(points, polygons[face.index()]);
+            assert!(diagram.face_halfedges(face).all(|halfedge| diagram.face(halfedge) == Some(face)));
+        }
+
+        // Every halfedge leaving a vertex comes up once around it, and the
+        // faces around it are in counterclockwise order
+        for vertex in diagram.vertices() {
+            let mut around: Vec<_> = diagram.vertex_halfedges(vertex).collect();
+            let mut leaving: Vec<_> = diagram.halfedges().filter(|halfedge| diagram.origin(*halfedge) == Some(vertex)).collect();
+            assert!(around.iter().all(|halfedge| diagram.origin(*halfedge) == Some(vertex)));
+            around.sort();
+            leaving.sort();
+            assert_eq!(around, leaving);
+
+            let (x, y) = diagram.position(vertex);
+            let faces: Vec<_> = diagram.vertex_faces(vertex).collect();
+            let is_inside = x > 1e-9 && x < 10. - 1e-9 && y > 1e-9 && y < 10. - 1e-9;
+            assert!(faces.len() >= if is_inside { 3 } else { 1 });
+            if is_inside {
+                let angles: Vec<f64> = faces.iter().map(|face| {
+                    let (site_x, site_y) = diagram.site(*face);
+                    (site_y - y).atan2(site_x - x)
+                }).collect();
+                let turns = (0..angles.len()).filter(|i| angles[(i + 1) % angles.len()] < angles[*i]).count();
+                assert_eq!(turns, 1);
+            }
+        }
+    }
+
+    #[test]
+    fn it_walks_the_unclipped_diagram() {
+        // Every face of three sites goes on to infinity, between two rays
+        let sites = vec![InputSite { x: 0., y: 0. }, InputSite { x: 2., y: 0. }, InputSite { x: 1., y: 2. }];
+        let diagram = Voronoi::new(sites, 0., 0., 0., 0.).try_run_unclipped().unwrap();
+        for face in diagram.faces() {
+            let halfedges: Vec<_> = diagram.face_halfedges(face).collect();
+            assert_eq!(halfedges.len(), 2);
+            let incoming = *halfedges.iter().find(|halfedge| diagram.origin(**halfedge).is_none()).unwrap();
+            let outgoing = *halfedges.iter().find(|halfedge| **halfedge != incoming).unwrap();
+            assert_eq!(diagram.origin(outgoing), Some(diagram.vertices().next().unwrap()));
+            assert_eq!(diagram.origin(diagram.twin(incoming)), diagram.origin(outgoing));
+            assert_eq!(diagram.origin(diagram.twin(outgoing)), None);
+            assert_eq!(diagram.cell_metrics(face), None);
+        }
+
+        let mut rng = StdRng::seed_from_u64(12);
+        let sites: Vec<InputSite> = (0..30).map(|_| InputSite { x: rng.gen::<f64>() * 10., y: rng.gen::<f64>() * 10. }).collect();
+        let diagram = Voronoi::new(sites, 0., 0., 0., 0.).try_run_unclipped().unwrap();
+        let mut rays = 0;
+        for face in diagram.faces() {
+            for halfedge in diagram.face_halfedges(face) {
+                match diagram.origin(halfedge) {
+                    Some(vertex) => assert!(diagram.position(vertex).0.is_finite()),
+                    None => rays += 1
+                }
+            }
+        }
+        // Each open face has one ray coming in
+        assert_eq!(rays, diagram.faces().filter(|face| diagram.cell_metrics(*face).is_none()).count());
+    }
+
+    #[test]
+    fn it_lists_cells_by_input_index() {
+        // The third site repeats the first, and the last one's cell is
+        // entirely outside the box
+        let sites = vec![
+            InputSite { x: 1., y: 1. },
+            InputSite { x: 3., y: 1. },
+            InputSite { x: 1., y: 1. },
+            InputSite { x: 2., y: 3. },
+            InputSite { x: 2., y: 20. }
+        ];
+        let mut voronoi = Voronoi::new(sites, 0., 0., 4., 4.);
+        voronoi.merge_duplicates();
+        let diagram = voronoi.try_run().unwrap();
+        let cells: Vec<(usize, Cell)> = diagram.cells().collect();
+
+        assert_eq!(cells.iter().map(|(input, _)| *input).collect::<Vec<_>>(), vec![0, 1, 2, 3, 4]);
+        assert_eq!(cells[0].1, cells[2].1);
+        assert_eq!(cells[4].1, Cell::Outside);
+        for (input, cell) in cells[..4].iter() {
+            match cell {
+                Cell::Polygon(polygon) => assert!(polygon.len() >= 3, "site {} has {:?}", input, polygon),
+                _ => panic!("Expected site {} to have a single polygon", input)
+            }
+        }
+    }
+
+    #[test]
+    fn it_lists_unbounded_cells() {
+        // The site in the middle is closed in by the others, which go on to
+        // infinity
+        let sites = vec![
+            InputSite { x: 0., y: 0. },
+            InputSite { x: 4., y: 0. },
+            InputSite { x: 4., y: 4. },
+            InputSite { x: 0., y: 4. },
+            InputSite { x: 2., y: 2. }
+        ];
+        let diagram = Voronoi::new(sites, 0., 0., 0., 0.).try_run_unclipped().unwrap();
+        let cells: Vec<Cell> = diagram.cells().map(|(_, cell)| cell).collect();
+        assert_eq!(cells[..4], vec![Cell::Unbounded; 4][..]);
+        match &cells[4] {
+            Cell::Polygon(polygon) => assert_eq!(polygon.len(), 4),
+            cell => panic!("Expected the middle site to have a polygon, got {:?}", cell)
+        }
+
+        let sites = vec![InputSite { x: 0., y: 0. }, InputSite { x: 2., y: 0. }, InputSite { x: 1., y: 2. }];
+        let diagram = Voronoi::new(sites, 0., 0., 0., 0.).try_run_unclipped().unwrap();
+        assert!(diagram.cells().all(|(_, cell)| cell == Cell::Unbounded));
+        let diagram = Voronoi::new(vec![InputSite { x: 1., y: 1. }], 0., 0., 0., 0.).try_run_unclipped().unwrap();
+        assert_eq!(diagram.cells().collect::<Vec<_>>(), vec![(0, Cell::Unbounded)]);
+    }
+
+    #[test]
+    fn it_clips_one_diagram_to_many_regions() {
+        let mut rng = StdRng::seed_from_u64(16);
+        let points: Vec<(f64, f64)> = (0..50).map(|_| (rng.gen::<f64>() * 10., rng.gen::<f64>() * 10.)).collect();
+        let sites = || points.iter().map(|&(x, y)| InputSite { x, y }).collect();
+        let diagram = Voronoi::new(sites(), 0., 0., 0., 0.).try_run_unclipped().unwrap();
+
+        // Tiles of the same diagram match diagrams built for each tile
+        for (x, y) in [(0., 0.), (2.5, 0.), (5., 7.5), (-1., -1.)].iter() {
+            let tile = diagram.clip(&BoundingBox::new(*x, *y, x + 2.5, y + 2.5)).unwrap();
+            let expected = Voronoi::try_build(sites(), *x, *y, x + 2.5, y + 2.5).unwrap();
+            assert_eq!(tile.get_polygons(), expected.get_polygons());
+        }
+        let disk = diagram.clip(&Circle::new(5., 5., 3.)).unwrap();
+        let expected = Voronoi::with_region(sites(), Circle::new(5., 5., 3.)).try_run().unwrap();
+        assert_eq!(disk.get_cell_edges(), expected.get_cell_edges());
+
+        assert!(diagram.clip(&BoundingBox::new(1., 1., 1., 2.)).is_err());
+
+        // A diagram that's already been clipped can't be clipped again
+        match disk.clip(&BoundingBox::new(0., 0., 10., 10.)) {
+            Err(VoronoiError::DegenerateConfiguration(_)) => {},
+            other => panic!("Expected a degenerate configuration, got {:?}", other.map(|_| ()))
+        }
+    }
+
+    #[test]
+    fn it_writes_polygons_with_options() {
+        // The region has a corner that's in line with its neighbors, and a hole
+        let exterior = vec![(2., 2.), (0., 2.), (0., 0.), (1., 0.), (2., 0.)];
+        let hole = vec![(0.5, 0.5), (1.5, 0.5), (1.5, 1.5), (0.5, 1.5)];
+        let sites = vec![InputSite { x: 1., y: 0.25 }];
+        let diagram = Voronoi::with_region(sites, PolygonRegion::new(exterior, vec![hole])).try_run().unwrap();
+
+        let options = PolygonOptions { start_at_lowest: true, ..PolygonOptions::default() };
+        assert_eq!(diagram.get_polygons_with(&options), vec![vec![(0., 0.), (1., 0.), (2., 0.), (2., 2.), (0., 2.)]]);
+
+        let options = PolygonOptions { winding: Winding::Clockwise, closed: true, start_at_lowest: true, remove_collinear: true };
+        let parts = diagram.get_multipolygons_with(&options);
+        assert_eq!(parts[0].len(), 1);
+        assert_eq!(parts[0][0].exterior, vec![(0., 0.), (0., 2.), (2., 2.), (2., 0.), (0., 0.)]);
+        assert_eq!(parts[0][0].holes, vec![vec![(0.5, 0.5), (1.5, 0.5), (1.5, 1.5), (0.5, 1.5), (0.5, 0.5)]]);
+    }
+
+    #[test]
+    fn it_builds_the_delaunay_triangulation() {
+        // Four triangles around a site in the middle of a square
+        let sites = vec![
+            InputSite { x: 0., y: 0. },
+            InputSite { x: 4., y: 0. },
+            InputSite { x: 4., y: 4. },
+            InputSite { x: 0., y: 4. },
+            InputSite { x: 2., y: 2. }
+        ];
+        let triangulation = Voronoi::try_build(sites, 0., 0., 4., 4.).unwrap().get_triangulation();
+        let mut triangles: Vec<Vec<usize>> = triangulation.triangles.iter().map(|triangle| {
+            let start = (0..3).min_by_key(|i| triangle[*i]).unwrap();
+            (0..3).map(|i| triangle[(start + i) % 3]).collect()
+        }).collect();
+        triangles.sort();
+        assert_eq!(triangles, vec![vec![0, 1, 4], vec![0, 4, 3], vec![1, 2, 4], vec![2, 3, 4]]);
+        for (triangle, neighbors) in triangulation.neighbors.iter().enumerate() {
+            // Each triangle borders two others, across its sides at the middle
+            let middle = triangulation.triangles[triangle].iter().position(|site| *site == 4).unwrap();
+            assert!(neighbors[middle].is_some() && neighbors[(middle + 2) % 3].is_some());
+            assert_eq!(neighbors[(middle + 1) % 3], None);
+            for neighbor in neighbors.iter().flatten() {
+                assert!(triangulation.neighbors[*neighbor].contains(&Some(triangle)));
+            }
+        }
+
+        // Cocircular sites, some of them off by rounding, are still split into
+        // triangles that cover their convex hull exactly once
+        for count in [4, 6, 12].iter() {
+            let points: Vec<(f64, f64)> = (0..*count).map(|i| {
+                let angle = i as f64 * 2. * std::f64::consts::PI / *count as f64;
+                (angle.cos(), angle.sin())
+            }).collect();
+            let sites = points.iter().map(|&(x, y)| InputSite { x, y }).collect();
+            let triangulation = Voronoi::try_build(sites, -2., -2., 2., 2.).unwrap().get_triangulation();
+            assert_eq!(triangulation.triangles.len(), count - 2);
+            let area: f64 = triangulation.triangles.iter().map(|[a, b, c]| {
+                let area = orient2d(points[*a], points[*b], points[*c]) / 2.;
+                assert!(area > 0.);
+                area
+            }).sum();
+            let expected = *count as f64 * (2. * std::f64::consts::PI / *count as f64).sin() / 2.;
+            assert!((area - expected).abs() < 1e-12, "{} != {}", area, expected);
+        }
+
+        // Collinear sites have no triangles
+        let sites = vec![InputSite { x: 0., y: 0. }, InputSite { x: 1., y: 1. }, InputSite { x: 2., y: 2. }];
+        assert!(Voronoi::try_build(sites, 0., 0., 2., 2.).unwrap().get_triangulation().triangles.is_empty());
+    }
+
+    #[test]
+    fn it_lists_the_neighbors_of_sites() {
+        // A 3x3 grid, so the site in the middle has four neighbors and the
+        // corners have two
+        let mut sites = Vec::new();
+        for i in 0..3 {
+            for j in 0..3 {
+                sites.push(InputSite { x: i as f64, y: j as f64 });
+            }
+        }
+        let diagram = Voronoi::try_build(sites, -0.5, -0.5, 2.5, 2.5).unwrap();
+        let mut neighbors: Vec<(usize, f64)> = diagram.neighbors(4).map(|(site, info)| (site, info.length)).collect();
+        neighbors.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(neighbors, vec![(1, 1.), (3, 1.), (5, 1.), (7, 1.)]);
+        assert_eq!(diagram.neighbors(0).count(), 2);
+
+        let adjacency = diagram.get_adjacency();
+        assert_eq!(adjacency.offsets.len(), 10);
+        assert_eq!(adjacency.neighbors.len(), 24);
+        for site in 0..9 {
+            for i in adjacency.offsets[site]..adjacency.offsets[site + 1] {
+                // Every edge is listed from both sides, with the same length
+                let neighbor = adjacency.neighbors[i];
+                let back = (adjacency.offsets[neighbor]..adjacency.offsets[neighbor + 1])
+                    .find(|j| adjacency.neighbors[*j] == site)
+                    .unwrap();
+                assert_eq!(adjacency.lengths[back], adjacency.lengths[i]);
+            }
+        }
+
+        // Before clipping, sites on the hull share rays, and collinear sites
+        // share lines
+        let sites = vec![InputSite { x: 0., y: 0. }, InputSite { x: 1., y: 0. }, InputSite { x: 2., y: 0. }];
+        let diagram = Voronoi::new(sites, 0., 0., 0., 0.).try_run_unclipped().unwrap();
+        let neighbors: Vec<(usize, f64)> = diagram.neighbors(1).map(|(site, info)| (site, info.length)).collect();
+        assert_eq!(neighbors.len(), 2);
+        assert!(neighbors.iter().all(|(_, length)| *length == f64::INFINITY));
+    }
+
+    #[test]
+    fn it_inserts_sites() {
+        let build = |sites: &[(f64, f64)], region: &dyn ClipRegion| {
+            let input = sites.iter().map(|&(x, y)| InputSite { x, y }).collect();
+            Voronoi::new(input, 0., 0., 0., 0.).try_run_unclipped().unwrap().clip(region).unwrap()
         };
-        self.edges_by_site_pair.insert(SitePair(a.id, b.id), new_edge);
-    }
-
-    fn add_boundary_sites(&mut self) {
-        // Add four "Boundary sites" so that all given sites have complete faces
-        let width = self.bounding_box.width();
-        let height = self.bounding_box.width();
-        let mid_x = self.bounding_box.mid_x();
-        let mid_y = self.bounding_box.mid_y();
-
-        // Left
-        self.sites.push(Site { x: mid_x - width * 5., y: mid_y, id: self.sites.len() });
-        // Right
-        self.sites.push(Site { x: mid_x + width * 5., y: mid_y, id: self.sites.len() });
-        // Top
-        self.sites.push(Site { x: mid_x, y: mid_y - height * 5., id: self.sites.len() });
-        // Bottom
-        self.sites.push(Site { x: mid_x, y: mid_y + height * 5., id: self.sites.len() });
+        let sorted = |triangles: Vec<[usize; 3]>| {
+            let mut triangles: Vec<[usize; 3]> = triangles.into_iter().map(|triangle| {
+                let first = (0..3).min_by_key(|i| triangle[*i]).unwrap();
+                [triangle[first], triangle[(first + 1) % 3], triangle[(first + 2) % 3]]
+            }).collect();
+            triangles.sort();
+            triangles
+        };
+
+        // Inserting sites one by one ends up the same as building with all of
+        // them, in a box, a circle and a polygon with a hole
+        let regions: Vec<Box<dyn ClipRegion>> = vec![
+            Box::new(BoundingBox::new(0., 0., 10., 10.)),
+            Box::new(Circle::new(5., 5., 4.)),
+            Box::new(PolygonRegion::new(vec![(0., 0.), (10., 0.), (10., 10.), (5., 6.), (0., 10.)], vec![vec![(3., 2.), (3., 4.), (7., 4.), (7., 2.)]]))
+        ];
+        let mut rng = StdRng::seed_from_u64(25);
+        for region in &regions {
+            let mut sites: Vec<(f64, f64)> = (0..12).map(|_| (rng.gen::<f64>() * 12. - 1., rng.gen::<f64>() * 12. - 1.)).collect();
+            let mut diagram = build(&sites, region.as_ref());
+            // The last one is on the edge of the hole, halfway between two sites
+            let inserted: Vec<(f64, f64)> = (0..8).map(|_| (rng.gen::<f64>() * 12. - 1., rng.gen::<f64>() * 12. - 1.)).chain(vec![(4., 1.), (4., 3.)]).collect();
+            for (x, y) in inserted {
+                assert_eq!(diagram.insert_site(x, y).unwrap(), sites.len());
+                sites.push((x, y));
+                let rebuilt = build(&sites, region.as_ref());
+                for (a, b) in diagram.get_cell_metrics().iter().zip(&rebuilt.get_cell_metrics()) {
+                    match (a, b) {
+                        (Some(a), Some(b)) => {
+                            assert!((a.area - b.area).abs() < 1e-9);
+                            assert!((a.perimeter - b.perimeter).abs() < 1e-9);
+                            assert!((a.centroid.0 - b.centroid.0).abs() < 1e-9 && (a.centroid.1 - b.centroid.1).abs() < 1e-9);
+                        },
+                        (None, None) => {},
+                        (a, b) => panic!("Expected the same cells, got {:?} and {:?}", a, b)
+                    }
+                }
+                assert_eq!(sorted(diagram.get_triangulation().triangles), sorted(rebuilt.get_triangulation().triangles));
+
+                // Vertices cut away with the old parts of cells aren't listed
+                assert_eq!(diagram.vertices().count(), rebuilt.vertices().count());
+                assert!(diagram.vertices().all(|vertex| diagram.vertex_halfedges(vertex).next().is_some()));
+            }
+        }
+
+        // Many sites added to a few, most of them past the hull of the ones
+        // before
+        let mut sites = vec![(5., 5.), (5.5, 5.), (5., 5.5)];
+        let mut diagram = build(&sites, &BoundingBox::new(0., 0., 10., 10.));
+        for _ in 0..300 {
+            let (x, y) = (rng.gen::<f64>() * 10., rng.gen::<f64>() * 10.);
+            diagram.insert_site(x, y).unwrap();
+            sites.push((x, y));
+        }
+        let rebuilt = build(&sites, &BoundingBox::new(0., 0., 10., 10.));
+        assert_eq!(sorted(diagram.get_triangulation().triangles), sorted(rebuilt.get_triangulation().triangles));
+        assert_eq!(diagram.get_triangulation().neighbors.len(), rebuilt.get_triangulation().neighbors.len());
+
+        // Sites that are the same as another one give an error, like they do
+        // when building
+        let mut diagram = build(&[(1., 1.), (2., 3.), (3., 1.)], &BoundingBox::new(0., 0., 4., 4.));
+        assert_eq!(diagram.insert_site(2., 3.).unwrap_err(), VoronoiError::DuplicateSites { first: 1, second: 3 });
+        assert_eq!(diagram.insert_site(2. + 1e-13, 3.).unwrap_err(), VoronoiError::DuplicateSites { first: 1, second: 3 });
+        let mut diagram = build(&[(1., 1.), (2., 3.)], &BoundingBox::new(0., 0., 4., 4.));
+        assert_eq!(diagram.insert_site(2., 3.).unwrap_err(), VoronoiError::DuplicateSites { first: 1, second: 2 });
+        assert!(matches!(diagram.insert_site(f64::NAN, 1.), Err(VoronoiError::NonFiniteCoordinate { .. })));
+
+        // Only diagrams clipped to a region without radii can take new sites
+        let sites = || vec![InputSite { x: 1., y: 1. }, InputSite { x: 2., y: 3. }];
+        let mut unclipped = Voronoi::new(sites(), 0., 0., 4., 4.).try_run_unclipped().unwrap();
+        assert!(unclipped.insert_site(3., 1.).is_err());
+        let mut voronoi = Voronoi::new(sites(), 0., 0., 4., 4.);
+        voronoi.set_radii(vec![1., 1.]);
+        assert!(voronoi.try_run().unwrap().insert_site(3., 1.).is_err());
+    }
+
+    #[test]
+    fn it_builds_the_unbounded_diagram() {
+        let sites = vec![
+            InputSite { x: 0., y: 0. },
+            InputSite { x: 2., y: 0. },
+            InputSite { x: 1., y: 2. }
+        ];
+        let diagram = Voronoi::build_unbounded(sites);
+        assert_eq!(diagram.vertices, vec![(1., 0.75)]);
+        assert_eq!(diagram.edges.len(), 3);
+        for edge in diagram.edges.iter() {
+            match edge.edge {
+                Edge::Half(x, y, dx, dy) => {
+                    assert_eq!((x, y), (1., 0.75));
+                    // Each ray points away from the site that isn't next to it,
+                    // and has the first site on its left
+                    let (left, right) = edge.sites;
+                    let other = 3 - left - right;
+                    let (other_x, other_y) = [(0., 0.), (2., 0.), (1., 2.)][other];
+                    assert!(dx * (other_x - x) + dy * (other_y - y) < 0.);
+                    let (left_x, left_y) = [(0., 0.), (2., 0.), (1., 2.)][left];
+                    assert!(dx * (left_y - y) - dy * (left_x - x) > 0.);
+                },
+                _ => panic!("Expected only rays, got {:?}", edge)
+            }
+        }
+        // Every cell comes in along one ray and leaves along the next one
+        // counterclockwise
+        for cell in diagram.cells.iter() {
+            match cell {
+                UnboundedCell::Open { incoming: (dx1, dy1), vertices, outgoing: (dx2, dy2) } => {
+                    assert_eq!(vertices, &vec![(1., 0.75)]);
+                    assert!(dx1 * dy2 - dy1 * dx2 > 0.);
+                },
+                _ => panic!("Expected an open cell, got {:?}", cell)
+            }
+        }
+
+        // The cell in the middle is closed
+        let sites = vec![
+            InputSite { x: 0., y: 0. },
+            InputSite { x: 4., y: 0. },
+            InputSite { x: 4., y: 4. },
+            InputSite { x: 0., y: 4. },
+            InputSite { x: 2., y: 2. }
+        ];
+        let diagram = Voronoi::build_unbounded(sites);
+        match &diagram.cells[4] {
+            UnboundedCell::Closed(vertices) => assert_eq!(rotated(vertices), vec![(0., 2.), (2., 0.), (4., 2.), (2., 4.)]),
+            cell => panic!("Expected a closed cell, got {:?}", cell)
+        }
+        match &diagram.cells[0] {
+            UnboundedCell::Open { incoming, vertices, outgoing } => {
+                assert_eq!(vertices, &vec![(2., 0.), (0., 2.)]);
+                assert!(incoming.0.abs() < 1e-12 && incoming.1 > 0.);
+                assert!(outgoing.0 < 0. && outgoing.1.abs() < 1e-12);
+            },
+            cell => panic!("Expected an open cell, got {:?}", cell)
+        }
+
+        // Collinear sites are separated by lines
+        let sites = vec![InputSite { x: 0., y: 0. }, InputSite { x: 1., y: 1. }, InputSite { x: 2., y: 2. }];
+        let diagram = Voronoi::build_unbounded(sites);
+        assert!(diagram.vertices.is_empty());
+        let mut lines: Vec<(usize, usize)> = diagram.edges.iter().map(|edge| {
+            match edge.edge {
+                Edge::Line(..) => (edge.sites.0.min(edge.sites.1), edge.sites.0.max(edge.sites.1)),
+                _ => panic!("Expected only lines, got {:?}", edge)
+            }
+        }).collect();
+        lines.sort();
+        assert_eq!(lines, vec![(0, 1), (1, 2)]);
+        let line_counts: Vec<usize> = diagram.cells.iter().map(|cell| match cell {
+            UnboundedCell::Lines(lines) => lines.len(),
+            _ => panic!("Expected only lines, got {:?}", cell)
+        }).collect();
+        assert_eq!(line_counts, vec![1, 2, 1]);
+
+        let diagram = Voronoi::build_unbounded(vec![InputSite { x: 1., y: 1. }]);
+        assert_eq!(diagram.cells, vec![UnboundedCell::Lines(Vec::new())]);
+    }
+
+    #[test]
+    fn it_merges_cocircular_vertices() {
+        // A grid is full of cocircular sites, so every interior Voronoi vertex
+        // should have degree 4
+        let mut sites = Vec::new();
+        for i in 0..5 {
+            for j in 0..5 {
+                sites.push(InputSite { x: i as f64, y: j as f64 });
+            }
+        }
+        let dcel = Voronoi::try_build(sites, -1., -1., 5., 5.).unwrap();
+
+        let edges = dcel.get_edges();
+        assert!(edges.iter().all(|&(x1, y1, x2, y2)| x1 != x2 || y1 != y2), "Found a zero-length edge");
+
+        // Merged vertices share their coordinates exactly, so we can count them
+        let mut endpoints: Vec<(f64, f64)> = edges.iter()
+            .flat_map(|&(x1, y1, x2, y2)| vec![(x1, y1), (x2, y2)])
+            .filter(|&(x, y)| x > 0. && x < 4. && y > 0. && y < 4.)
+            .collect();
+        endpoints.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let mut vertices = endpoints.clone();
+        vertices.dedup();
+        assert_eq!(vertices.len(), 16);
+        for vertex in vertices {
+            assert_eq!(endpoints.iter().filter(|endpoint| **endpoint == vertex).count(), 4);
+        }
+
+        // A square with a corner off by an ulp isn't cocircular, so its two
+        // vertices stay apart with a tiny edge between them
+        let up = f64::from_bits(1_f64.to_bits() + 1);
+        for (corner, count) in [(1., 1), (up, 2)].iter() {
+            let sites = vec![InputSite { x: 0., y: 0. }, InputSite { x: 1., y: 0. }, InputSite { x: 1., y: *corner }, InputSite { x: 0., y: 1. }];
+            let diagram = Voronoi::try_build_unbounded(sites).unwrap();
+            assert_eq!(diagram.vertices.len(), *count);
+            assert_eq!(diagram.edges.len(), 3 + count);
+        }
+    }
+
+    #[test]
+    fn it_starts_with_several_sites_at_the_lowest_y() {
+        // The first four sites are level, so the sweep starts with four arcs
+        // separated by vertical bisectors at x = 0.5, 1.5 and 2.5
+        let sites = vec![
+            InputSite { x: 2., y: 0. },
+            InputSite { x: 0., y: 0. },
+            InputSite { x: 3., y: 0. },
+            InputSite { x: 1., y: 0. },
+            InputSite { x: 1., y: 2. },
+            InputSite { x: 2., y: 2. }
+        ];
+        let mut voronoi = Voronoi::new(sites, -1., -1., 4., 4.);
+        voronoi.sweep().unwrap();
+
+        // Only the horizontal bisectors between the two rows are bounded
+        let mut edges: Vec<(f64, f64, f64, f64)> = voronoi.dcel.get_edges().iter().map(|&(x1, y1, x2, y2)| {
+            if (x1, y1) < (x2, y2) { (x1, y1, x2, y2) } else { (x2, y2, x1, y1) }
+        }).collect();
+        edges.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(edges, vec![(0.5, 1., 1.5, 1.), (1.5, 1., 2.5, 1.)]);
     }
 }
@@ -0,0 +1,264 @@
+// Finds the cell that a point is in. The nearest site is found by walking the
+// Delaunay triangulation, see `walk_to_nearest`. A coarse grid of the sites
+// gives each walk a start close to the point, so it only takes a few steps.
+use crate::dcel::{Dcel, FaceId};
+use crate::predicates::orient2d;
+use crate::SiteId;
+
+pub struct PointLocator<'a> {
+    dcel: &'a Dcel,
+    // The Delaunay neighbors of each site, in compressed sparse rows like
+    // `Adjacency`, which are quicker to walk than the triangles
+    offsets: Vec<usize>,
+    neighbors: Vec<SiteId>,
+    // The site nearest to the middle of each square of the grid, by rows
+    grid: Vec<SiteId>,
+    columns: usize,
+    rows: usize,
+    origin: (f64, f64),
+    size: (f64, f64)
+}
+
+impl<'a> PointLocator<'a> {
+    pub fn new(dcel: &'a Dcel) -> PointLocator<'a> {
+        let sites: Vec<(f64, f64)> = dcel.faces().map(|face| dcel.site(face)).collect();
+
+        // Sites that are all on a line have no triangles, and each one's
+        // neighbors are the ones next to it along the line
+        let mut pairs = Vec::new();
+        let triangles = dcel.get_triangulation().triangles;
+        if triangles.is_empty() {
+            let mut order: Vec<SiteId> = (0..sites.len()).collect();
+            order.sort_by(|a, b| sites[*a].partial_cmp(&sites[*b]).unwrap());
+            for pair in order.windows(2) {
+                pairs.push((pair[0], pair[1]));
+                pairs.push((pair[1], pair[0]));
+            }
+        }
+        for triangle in triangles {
+            for i in 0..3 {
+                pairs.push((triangle[i], triangle[(i + 1) % 3]));
+                pairs.push((triangle[(i + 1) % 3], triangle[i]));
+            }
+        }
+        pairs.sort_unstable();
+        pairs.dedup();
+        let mut offsets = vec![0; sites.len() + 1];
+        for (site, _) in &pairs {
+            offsets[site + 1] += 1;
+        }
+        for site in 0..sites.len() {
+            offsets[site + 1] += offsets[site];
+        }
+
+        let (mut min_x, mut min_y, mut max_x, mut max_y) = (f64::INFINITY, f64::INFINITY, -f64::INFINITY, -f64::INFINITY);
+        for (x, y) in &sites {
+            min_x = min_x.min(*x);
+            min_y = min_y.min(*y);
+            max_x = max_x.max(*x);
+            max_y = max_y.max(*y);
+        }
+        // About one site per square
+        let side = (sites.len() as f64).sqrt().ceil().max(1.) as usize;
+        let mut locator = PointLocator {
+            dcel,
+            offsets,
+            neighbors: pairs.into_iter().map(|(_, neighbor)| neighbor).collect(),
+            grid: Vec::new(),
+            columns: side,
+            rows: side,
+            origin: (min_x, min_y),
+            size: ((max_x - min_x) / side as f64, (max_y - min_y) / side as f64)
+        };
+        if sites.is_empty() {
+            return locator;
+        }
+
+        // Walk from each square to the next one, snaking along the rows so
+        // that each walk is short
+        let mut site = 0;
+        let mut grid = vec![0; side * side];
+        for row in 0..side {
+            for i in 0..side {
+                let column = if row % 2 == 0 { i } else { side - 1 - i };
+                let x = min_x + (column as f64 + 0.5) * locator.size.0;
+                let y = min_y + (row as f64 + 0.5) * locator.size.1;
+                site = locator.walk(x, y, site);
+                grid[row * side + column] = site;
+            }
+        }
+        locator.grid = grid;
+        locator
+    }
+
+    // Returns the site whose cell a point is in, or None if it's outside the
+    // diagram. Points on an edge can go to the cell on either side.
+    pub fn locate(&self, x: f64, y: f64) -> Option<SiteId> {
+        let site = self.nearest_site(x, y)?;
+        if self.is_in_cell(site, x, y) { Some(site) } else { None }
+    }
+
+    // Locates many points at once, see `locate`. Each walk starts from the
+    // last point's site when that's nearer than the grid's, so points that
+    // follow each other around (like a track) are quickest.
+    pub fn locate_all(&self, points: &[(f64, f64)]) -> Vec<Option<SiteId>> {
+        let mut last = None;
+        points.iter().map(|&(x, y)| {
+            let mut start = self.start(x, y)?;
+            if let Some(last) = last {
+                if self.distance(last, x, y) < self.distance(start, x, y) {
+                    start = last;
+                }
+            }
+            let site = self.walk(x, y, start);
+            last = Some(site);
+            if self.is_in_cell(site, x, y) { Some(site) } else { None }
+        }).collect()
+    }
+
+    // Returns the site nearest to a point, whether or not its cell reaches
+    // that far. None if there are no sites or the point isn't a number.
+    pub fn nearest_site(&self, x: f64, y: f64) -> Option<SiteId> {
+        Some(self.walk(x, y, self.start(x, y)?))
+    }
+
+    // The site of the grid square that a point is in, or the square nearest to
+    // it
+    fn start(&self, x: f64, y: f64) -> Option<SiteId> {
+        if self.grid.is_empty() || x.is_nan() || y.is_nan() {
+            return None;
+        }
+        let square = |value: f64, origin: f64, size: f64, count: usize| {
+            if size > 0. { (((value - origin) / size).max(0.) as usize).min(count - 1) } else { 0 }
+        };
+        let column = square(x, self.origin.0, self.size.0, self.columns);
+        let row = square(y, self.origin.1, self.size.1, self.rows);
+        Some(self.grid[row * self.columns + column])
+    }
+
+    fn walk(&self, x: f64, y: f64, site: SiteId) -> SiteId {
+        walk_to_nearest(self.dcel, (x, y), site, |site, visit| {
+            for neighbor in &self.neighbors[self.offsets[site]..self.offsets[site + 1]] {
+                visit(*neighbor);
+            }
+        })
+    }
+
+    // The squared distance from a site to a point
+    fn distance(&self, site: SiteId, x: f64, y: f64) -> f64 {
+        let (site_x, site_y) = self.dcel.site(FaceId(site));
+        (site_x - x) * (site_x - x) + (site_y - y) * (site_y - y)
+    }
+
+    // Whether a point in the Voronoi cell of a site is in what's left of it
+    // after clipping. Counts how many times a ray to the right crosses the
+    // chords of the face's edges, and then flips that for every arc whose
+    // segment (between it and its chord) has the point in it.
+    fn is_in_cell(&self, site: SiteId, x: f64, y: f64) -> bool {
+        let dcel = self.dcel;
+        let face = FaceId(site);
+        if !dcel.is_clipped() || dcel.face_halfedges(face).any(|halfedge| dcel.next(halfedge).is_none()) {
+            return true;
+        }
+        let mut inside = false;
+        for halfedge in dcel.face_halfedges(face) {
+            let (a, b) = match (dcel.origin(halfedge), dcel.origin(dcel.twin(halfedge))) {
+                (Some(a), Some(b)) => (dcel.position(a), dcel.position(b)),
+                _ => return true
+            };
+            if (a.1 > y) != (b.1 > y) && x < a.0 + (y - a.1) / (b.1 - a.1) * (b.0 - a.0) {
+                inside = !inside;
+            }
+            if let Some((center_x, center_y, radius)) = dcel.arc(halfedge) {
+                let in_circle = (x - center_x).hypot(y - center_y) < radius;
+                if in_circle && (a == b || orient2d(a, b, (x, y)) < 0.) {
+                    inside = !inside;
+                }
+            }
+        }
+        inside
+    }
+}
+
+// Walks from a site to the site nearest to a point: from any site that isn't
+// the nearest, one of its Delaunay neighbors is nearer still, so stepping to
+// the nearest neighbor until there's none nearer ends at the nearest site.
+// `neighbors` goes through the neighbors of a site.
+pub(crate) fn walk_to_nearest<F>(dcel: &Dcel, (x, y): (f64, f64), mut site: SiteId, neighbors: F) -> SiteId
+    where F: Fn(SiteId, &mut dyn FnMut(SiteId)) {
+    let distance = |site: SiteId| {
+        let (site_x, site_y) = dcel.site(FaceId(site));
+        (site_x - x) * (site_x - x) + (site_y - y) * (site_y - y)
+    };
+    let mut nearest = distance(site);
+    loop {
+        let (mut closer, mut closer_distance) = (site, nearest);
+        neighbors(site, &mut |neighbor| {
+            let neighbor_distance = distance(neighbor);
+            if neighbor_distance < closer_distance {
+                closer = neighbor;
+                closer_distance = neighbor_distance;
+            }
+        });
+        if closer == site {
+            return site;
+        }
+        site = closer;
+        nearest = closer_distance;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Voronoi, InputSite, Circle};
+    use crate::locate::PointLocator;
+    use rand::prelude::*;
+
+    #[test]
+    fn it_locates_points() {
+        fn nearest(sites: &[(f64, f64)], (x, y): (f64, f64)) -> usize {
+            (0..sites.len()).min_by(|a, b| {
+                let a = (sites[*a].0 - x).hypot(sites[*a].1 - y);
+                let b = (sites[*b].0 - x).hypot(sites[*b].1 - y);
+                a.partial_cmp(&b).unwrap()
+            }).unwrap()
+        }
+
+        let mut rng = StdRng::seed_from_u64(9);
+        let sites: Vec<(f64, f64)> = (0..200).map(|_| (rng.gen::<f64>() * 10., rng.gen::<f64>() * 10.)).collect();
+        let points: Vec<(f64, f64)> = (0..2000).map(|_| (rng.gen::<f64>() * 14. - 2., rng.gen::<f64>() * 14. - 2.)).collect();
+        let input = || sites.iter().map(|&(x, y)| InputSite { x, y }).collect();
+
+        let diagram = Voronoi::try_build(input(), 0., 0., 10., 10.).unwrap();
+        let locator = PointLocator::new(&diagram);
+        for &(x, y) in &points {
+            let inside = x > 0. && x < 10. && y > 0. && y < 10.;
+            assert_eq!(locator.locate(x, y), if inside { Some(nearest(&sites, (x, y))) } else { None });
+            assert_eq!(locator.nearest_site(x, y), Some(nearest(&sites, (x, y))));
+        }
+        let located = locator.locate_all(&points);
+        assert!(points.iter().zip(located).all(|(point, site)| locator.locate(point.0, point.1) == site));
+
+        // Cells clipped to a circle, and unclipped cells that go on forever
+        let diagram = Voronoi::with_region(input(), Circle::new(5., 5., 4.)).try_run().unwrap();
+        let locator = PointLocator::new(&diagram);
+        for &(x, y) in &points {
+            let inside = (x - 5.).hypot(y - 5.) < 4.;
+            assert_eq!(locator.locate(x, y), if inside { Some(nearest(&sites, (x, y))) } else { None });
+        }
+        let diagram = Voronoi::new(input(), 0., 0., 0., 0.).try_run_unclipped().unwrap();
+        let locator = PointLocator::new(&diagram);
+        assert!(points.iter().all(|&point| locator.locate(point.0, point.1) == Some(nearest(&sites, point))));
+
+        // Sites on a line have no triangles to walk, and no sites at all have
+        // nowhere to go
+        let sites = vec![InputSite { x: 0., y: 0. }, InputSite { x: 3., y: 1. }, InputSite { x: 6., y: 2. }];
+        let diagram = Voronoi::try_build(sites, -1., -1., 7., 3.).unwrap();
+        let locator = PointLocator::new(&diagram);
+        assert_eq!(locator.locate(6.5, -0.5), Some(2));
+        assert_eq!(locator.locate(2., 1.5), Some(1));
+        assert_eq!(locator.locate(8., 0.), None);
+        let diagram = Voronoi::try_build(Vec::new(), 0., 0., 1., 1.).unwrap();
+        assert_eq!(PointLocator::new(&diagram).locate(0.5, 0.5), None);
+    }
+}
@@ -1,3 +1,5 @@
+use crate::predicates::{orient2d, compare_arcs};
+
 const EPSILON: f64 = 1.00e-12_f64;
 
 // Returns the x coordinate of the intersection of two beach segments
@@ -14,7 +16,7 @@ const EPSILON: f64 = 1.00e-12_f64;
 //
 
 pub fn equals_with_epsilon(a: f64, b: f64) -> bool {
-    return (a - b).abs() < EPSILON;
+    (a - b).abs() < EPSILON
 }
 
 pub fn breakpoint_between(x1: f64, y1: f64, x2: f64, y2: f64, directrix: f64) -> f64 {
@@ -24,26 +26,70 @@ pub fn breakpoint_between(x1: f64, y1: f64, x2: f64, y2: f64, directrix: f64) ->
         // y's are equal, so just average x's to get x
         return (x1 + x2) / 2.0;
     }
-    let s = directrix;
-    let sqrt = -((s*s-s*y1-s*y2+y1*y2)*(x1*x1-2.0*x1*x2+x2*x2+y1*y1-2.0*y1*y2+y2*y2)).sqrt();
-    return (-sqrt+s*x1-s*x2-x1*y2+x2*y1)/(y1-y2);
+    // Sites at about the same y are processed in order of x, so the directrix
+    // can be a little below a focus. Its parabola is then a vertical line, and
+    // solving for one that opens downwards would give NaN.
+    let directrix = directrix.max(y1).max(y2);
+    // Solve relative to the first focus, so that coordinates far from the
+    // origin don't swamp the differences between them
+    let dx = x2 - x1;
+    let dy = y2 - y1;
+    let s = directrix - y1;
+    let sqrt = (s * (s - dy) * (dx * dx + dy * dy)).sqrt();
+    let offset = if s * dx > 0. {
+        // Multiply through by the conjugate to avoid cancellation
+        s * (dx * dx + dy * dy - s * dy) / (s * dx + sqrt)
+    } else {
+        (s * dx - sqrt) / dy
+    };
+    x1 + offset
+}
+
+// Whether a site is left of the breakpoint between the arcs of left and right
+// on the beach line, when the sweep line reaches the site. Decided exactly,
+// by which of the arcs is higher right below the site.
+pub fn is_left_of_breakpoint(left: (f64, f64), right: (f64, f64), site: (f64, f64)) -> bool {
+    // The arcs cross on either side of the focus nearer to the sweep line,
+    // whose arc is the higher one between the crossings. The breakpoint is the
+    // crossing with left's arc on its left.
+    let left_is_higher = compare_arcs(left, right, site) > 0.;
+    if left.1 < right.1 {
+        left_is_higher && site.0 < right.0
+    } else if left.1 > right.1 {
+        left_is_higher || site.0 < left.0
+    } else {
+        left_is_higher
+    }
+}
+
+pub fn is_right_of_breakpoint(left: (f64, f64), right: (f64, f64), site: (f64, f64)) -> bool {
+    // Mirror everything, which swaps left and right
+    is_left_of_breakpoint((-right.0, right.1), (-left.0, left.1), (-site.0, site.1))
 }
 
 // Finds the point equidistant to all given points, and also returns the
 // distance to that point
 pub fn find_center(x1: f64, y1: f64, x2: f64, y2: f64, x3: f64, y3: f64) -> Option<(f64, f64, f64)> {
-    let temp = x2 * x2 + y2 * y2;
-    let bc = (x1 * x1 + y1 * y1 - temp) / 2.0;
-    let cd = (temp - x3 * x3 - y3 * y3) / 2.0;
-    let det = (x1 - x2) * (y2 - y3) - (x2 - x3) * (y1 - y2);
-
-    // If determinant is 0, these points are colinear and there is no center
-    if det.abs() < EPSILON { return None; }
-
-    let cx = (bc * (y2 - y3) - cd * (y1 - y2)) / det;
-    let cy = ((x1 - x2) * cd - (x2 - x3) * bc) / det;
-    let dx = cx - x1;
-    let dy = cy - y1;
+    // If the points are exactly colinear there is no center. Otherwise the
+    // determinant's sign is right even when it's too small to compute directly.
+    let orientation = orient2d((x1, y1), (x2, y2), (x3, y3));
+    if orientation == 0. { return None; }
+
+    // Work relative to the first point, which keeps the products small when
+    // the points are close together but far from the origin
+    let bx = x2 - x1;
+    let by = y2 - y1;
+    let cx = x3 - x1;
+    let cy = y3 - y1;
+    let b_len = bx * bx + by * by;
+    let c_len = cx * cx + cy * cy;
+    let det = 2. * orientation;
+
+    let dx = (cy * b_len - by * c_len) / det;
+    let dy = (bx * c_len - cx * b_len) / det;
     let rad = (dx*dx + dy*dy).sqrt();
-    return Some((cx, cy, rad));
+
+    // Nearly colinear points can have a center too far away to represent
+    if !dx.is_finite() || !dy.is_finite() || !rad.is_finite() { return None; }
+    Some((x1 + dx, y1 + dy, rad))
 }
@@ -0,0 +1,200 @@
+// Area, centroid and other measures of the cells of a diagram. They're found by
+// integrating along the halfedges around each face (Green's theorem), so every
+// ring of a face counts, holes are subtracted, and arcs are exact.
+use crate::dcel::{Dcel, FaceId};
+use std::f64::consts::PI;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CellMetrics {
+    pub area: f64,
+    pub centroid: (f64, f64),
+    pub perimeter: f64,
+    // min_x, min_y, max_x, max_y
+    pub bounds: (f64, f64, f64, f64),
+    // The polar second moment of area about the centroid, i.e. the integral of
+    // the squared distance to the centroid over the cell
+    pub second_moment: f64,
+    // 4 pi area / perimeter^2, which is 1 for a disk and less for anything else
+    pub compactness: f64
+}
+
+// Integrals over a region, of 1, x, y, x^2, y^2 and xy
+#[derive(Default)]
+struct Moments {
+    area: f64,
+    x: f64,
+    y: f64,
+    xx: f64,
+    yy: f64,
+    xy: f64
+}
+
+impl Moments {
+    // Adds the triangle between the origin and a segment, signed so that
+    // counterclockwise rings add up to their area
+    fn add_segment(&mut self, (x1, y1): (f64, f64), (x2, y2): (f64, f64)) {
+        let cross = x1 * y2 - x2 * y1;
+        self.area += cross / 2.;
+        self.x += (x1 + x2) * cross / 6.;
+        self.y += (y1 + y2) * cross / 6.;
+        self.xx += (x1 * x1 + x1 * x2 + x2 * x2) * cross / 12.;
+        self.yy += (y1 * y1 + y1 * y2 + y2 * y2) * cross / 12.;
+        self.xy += (x1 * y2 + 2. * x1 * y1 + 2. * x2 * y2 + x2 * y1) * cross / 24.;
+    }
+
+    // Adds an arc counterclockwise around (x, y): the chord between its ends,
+    // and the circular segment between the chord and the arc. The segment is
+    // a sector minus a triangle, worked out with its axis along x and then
+    // turned to the direction of the middle of the arc.
+    fn add_arc(&mut self, from: (f64, f64), to: (f64, f64), (x, y): (f64, f64), radius: f64, start: f64, sweep: f64) {
+        self.add_segment(from, to);
+
+        let half = sweep / 2.;
+        let (sin, cos) = half.sin_cos();
+        let r2 = radius * radius;
+        let sector_area = r2 * half;
+        let triangle_area = r2 * sin * cos;
+        let area = sector_area - triangle_area;
+        let moment = 2. * r2 * radius * sin / 3. - triangle_area * 2. * radius * cos / 3.;
+        let along = r2 * r2 / 4. * (half + sin * cos) - triangle_area * r2 * cos * cos / 2.;
+        let across = r2 * r2 / 4. * (half - sin * cos) - triangle_area * r2 * sin * sin / 6.;
+
+        let (sin, cos) = (start + half).sin_cos();
+        let (moment_x, moment_y) = (cos * moment, sin * moment);
+        self.area += area;
+        self.x += area * x + moment_x;
+        self.y += area * y + moment_y;
+        self.xx += cos * cos * along + sin * sin * across + 2. * x * moment_x + x * x * area;
+        self.yy += sin * sin * along + cos * cos * across + 2. * y * moment_y + y * y * area;
+        self.xy += sin * cos * (along - across) + x * moment_y + y * moment_x + x * y * area;
+    }
+}
+
+impl Dcel {
+    // Measures the cell of a site, None if it's empty or isn't closed
+    pub fn cell_metrics(&self, face: FaceId) -> Option<CellMetrics> {
+        let halfedges: Vec<_> = self.face_halfedges(face).collect();
+        if halfedges.is_empty() || halfedges.iter().any(|halfedge| self.next(*halfedge).is_none()) {
+            return None;
+        }
+
+        // Work relative to the site, which keeps the products small for cells
+        // far from the origin
+        let (site_x, site_y) = self.site(face);
+        let mut moments = Moments::default();
+        let mut perimeter = 0.;
+        let mut bounds = (f64::INFINITY, f64::INFINITY, -f64::INFINITY, -f64::INFINITY);
+        let mut extend = |(x, y): (f64, f64)| {
+            bounds = (bounds.0.min(x), bounds.1.min(y), bounds.2.max(x), bounds.3.max(y));
+        };
+        for halfedge in halfedges {
+            let (x1, y1) = self.position(self.origin(halfedge)?);
+            let (x2, y2) = self.position(self.origin(self.twin(halfedge))?);
+            let from = (x1 - site_x, y1 - site_y);
+            let to = (x2 - site_x, y2 - site_y);
+            extend((x1, y1));
+            match self.arc(halfedge) {
+                Some((x, y, radius)) => {
+                    let start = (y1 - y).atan2(x1 - x);
+                    let mut sweep = ((y2 - y).atan2(x2 - x) - start).rem_euclid(2. * PI);
+                    if sweep == 0. {
+                        sweep = 2. * PI;
+                    }
+                    moments.add_arc(from, to, (x - site_x, y - site_y), radius, start, sweep);
+                    perimeter += radius * sweep;
+                    // The arc can bulge out past its ends
+                    for quarter in 0..4 {
+                        let angle = quarter as f64 * PI / 2.;
+                        if (angle - start).rem_euclid(2. * PI) < sweep {
+                            let (sin, cos) = angle.sin_cos();
+                            extend((x + radius * cos, y + radius * sin));
+                        }
+                    }
+                },
+                None => {
+                    moments.add_segment(from, to);
+                    perimeter += (x2 - x1).hypot(y2 - y1);
+                }
+            }
+        }
+        if moments.area <= 0. {
+            return None;
+        }
+
+        let (x, y) = (moments.x / moments.area, moments.y / moments.area);
+        Some(CellMetrics {
+            area: moments.area,
+            centroid: (site_x + x, site_y + y),
+            perimeter,
+            bounds,
+            second_moment: moments.xx + moments.yy - moments.area * (x * x + y * y),
+            compactness: 4. * PI * moments.area / (perimeter * perimeter)
+        })
+    }
+
+    // Measures the cell of every site, see `cell_metrics`
+    pub fn get_cell_metrics(&self) -> Vec<Option<CellMetrics>> {
+        self.faces().map(|face| self.cell_metrics(face)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Voronoi, InputSite, Circle, PolygonRegion};
+    use rand::prelude::*;
+
+    #[test]
+    fn it_measures_cells() {
+        use std::f64::consts::PI;
+
+        // The middle of a 3x3 grid is a unit square
+        let mut sites = Vec::new();
+        for i in 0..3 {
+            for j in 0..3 {
+                sites.push(InputSite { x: i as f64, y: j as f64 });
+            }
+        }
+        let metrics = Voronoi::try_build(sites, -0.5, -0.5, 2.5, 2.5).unwrap().get_cell_metrics();
+        let middle = metrics[4].unwrap();
+        assert!((middle.area - 1.).abs() < 1e-12);
+        assert!((middle.centroid.0 - 1.).abs() < 1e-12 && (middle.centroid.1 - 1.).abs() < 1e-12);
+        assert!((middle.perimeter - 4.).abs() < 1e-12);
+        let (min_x, min_y, max_x, max_y) = middle.bounds;
+        assert!([min_x - 0.5, min_y - 0.5, max_x - 1.5, max_y - 1.5].iter().all(|d| d.abs() < 1e-12));
+        assert!((middle.second_moment - 1. / 6.).abs() < 1e-12);
+        assert!((middle.compactness - PI / 4.).abs() < 1e-12);
+
+        // A lone site in a circle has the whole disk
+        let sites = vec![InputSite { x: 1., y: 1. }];
+        let disk = Voronoi::with_region(sites, Circle::new(3., 4., 2.)).try_run().unwrap().get_cell_metrics()[0].unwrap();
+        assert!((disk.area - 4. * PI).abs() < 1e-9);
+        assert!((disk.centroid.0 - 3.).abs() < 1e-9 && (disk.centroid.1 - 4.).abs() < 1e-9);
+        assert!((disk.second_moment - 8. * PI).abs() < 1e-9);
+        assert!((disk.compactness - 1.).abs() < 1e-9);
+        assert!((disk.bounds.0 - 1.).abs() < 1e-12 && (disk.bounds.3 - 6.).abs() < 1e-12);
+
+        // Cells cut by a circle add up to the disk, and their moments about
+        // the center do too
+        let mut rng = StdRng::seed_from_u64(5);
+        let sites: Vec<InputSite> = (0..30).map(|_| InputSite { x: rng.gen::<f64>() * 6. - 3., y: rng.gen::<f64>() * 6. - 3. }).collect();
+        let metrics = Voronoi::with_region(sites, Circle::new(0., 0., 2.)).try_run().unwrap().get_cell_metrics();
+        let cells: Vec<_> = metrics.iter().flatten().collect();
+        assert!(cells.len() < 30);
+        let area: f64 = cells.iter().map(|cell| cell.area).sum();
+        let x: f64 = cells.iter().map(|cell| cell.area * cell.centroid.0).sum();
+        let moment: f64 = cells.iter().map(|cell| cell.second_moment + cell.area * (cell.centroid.0.powi(2) + cell.centroid.1.powi(2))).sum();
+        assert!((area - 4. * PI).abs() < 1e-9);
+        assert!(x.abs() < 1e-9);
+        assert!((moment - 8. * PI).abs() < 1e-9, "{} != {}", moment, 8. * PI);
+
+        // Holes are taken out
+        let sites = vec![InputSite { x: 0.5, y: 0.5 }];
+        let outer = vec![(0., 0.), (4., 0.), (4., 4.), (0., 4.)];
+        let hole = vec![(1., 1.), (1., 3.), (3., 3.), (3., 1.)];
+        let metrics = Voronoi::with_region(sites, PolygonRegion::new(outer, vec![hole])).try_run().unwrap().get_cell_metrics();
+        let cell = metrics[0].unwrap();
+        assert!((cell.area - 12.).abs() < 1e-12);
+        assert!((cell.perimeter - 24.).abs() < 1e-12);
+        assert!((cell.centroid.0 - 2.).abs() < 1e-12 && (cell.centroid.1 - 2.).abs() < 1e-12);
+    }
+}
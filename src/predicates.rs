@@ -0,0 +1,324 @@
+// Robust geometric predicates, after Jonathan Shewchuk's "Adaptive Precision
+// Floating-Point Arithmetic and Fast Robust Geometric Predicates".
+//
+// Each predicate first evaluates its determinant with plain floating point
+// arithmetic and compares the result against an error bound. Only when the
+// result is too close to zero to trust is it recomputed exactly, using
+// expansions (sums of non-overlapping floats) that represent every
+// intermediate value without rounding. The sign of the returned value is
+// therefore always correct, even though its magnitude is only approximate.
+
+// Half an ulp of 1.0, the largest relative error of a single rounded operation
+const EPSILON: f64 = f64::EPSILON / 2.;
+// Used to split a float into two non-overlapping halves: 2^ceil(53 / 2) + 1
+const SPLITTER: f64 = 134_217_729.;
+
+const CCW_ERRBOUND_A: f64 = (3. + 16. * EPSILON) * EPSILON;
+const ICC_ERRBOUND_A: f64 = (10. + 96. * EPSILON) * EPSILON;
+const ARC_ERRBOUND_A: f64 = (8. + 64. * EPSILON) * EPSILON;
+
+// Returns a positive value if a, b and c are in counterclockwise order, a
+// negative value if they are in clockwise order and zero if they are collinear
+pub fn orient2d(a: (f64, f64), b: (f64, f64), c: (f64, f64)) -> f64 {
+    let detleft = (a.0 - c.0) * (b.1 - c.1);
+    let detright = (a.1 - c.1) * (b.0 - c.0);
+    let det = detleft - detright;
+
+    let detsum = if detleft > 0. {
+        if detright <= 0. { return det; }
+        detleft + detright
+    } else if detleft < 0. {
+        if detright >= 0. { return det; }
+        -detleft - detright
+    } else {
+        return det;
+    };
+
+    let errbound = CCW_ERRBOUND_A * detsum;
+    if det >= errbound || -det >= errbound {
+        return det;
+    }
+
+    orient2d_exact(a, b, c)
+}
+
+// Returns a positive value if d lies inside the circle through a, b and c, a
+// negative value if it lies outside and zero if the four points are cocircular.
+// a, b and c must be in counterclockwise order, otherwise the sign is reversed.
+pub fn incircle(a: (f64, f64), b: (f64, f64), c: (f64, f64), d: (f64, f64)) -> f64 {
+    let adx = a.0 - d.0;
+    let bdx = b.0 - d.0;
+    let cdx = c.0 - d.0;
+    let ady = a.1 - d.1;
+    let bdy = b.1 - d.1;
+    let cdy = c.1 - d.1;
+
+    let bdxcdy = bdx * cdy;
+    let cdxbdy = cdx * bdy;
+    let alift = adx * adx + ady * ady;
+
+    let cdxady = cdx * ady;
+    let adxcdy = adx * cdy;
+    let blift = bdx * bdx + bdy * bdy;
+
+    let adxbdy = adx * bdy;
+    let bdxady = bdx * ady;
+    let clift = cdx * cdx + cdy * cdy;
+
+    let det = alift * (bdxcdy - cdxbdy) + blift * (cdxady - adxcdy) + clift * (adxbdy - bdxady);
+
+    let permanent = (bdxcdy.abs() + cdxbdy.abs()) * alift
+        + (cdxady.abs() + adxcdy.abs()) * blift
+        + (adxbdy.abs() + bdxady.abs()) * clift;
+    let errbound = ICC_ERRBOUND_A * permanent;
+    if det > errbound || -det > errbound {
+        return det;
+    }
+
+    incircle_exact(a, b, c, d)
+}
+
+// Compares the parabolas with foci a and b and the horizontal line through s
+// as their directrix, where they pass below s (so both foci must be at or below
+// it). Returns a positive value if a's parabola is the higher one there, a
+// negative value if b's is and zero if they cross right below s. On the beach
+// line, that's whether s is under the arc of a or of b.
+pub fn compare_arcs(a: (f64, f64), b: (f64, f64), s: (f64, f64)) -> f64 {
+    // A parabola passes |s - focus|^2 / (2 * (s.y - focus.y)) below s, so a's is
+    // higher when |s - a|^2 * (s.y - b.y) < |s - b|^2 * (s.y - a.y)
+    let asx = s.0 - a.0;
+    let asy = s.1 - a.1;
+    let bsx = s.0 - b.0;
+    let bsy = s.1 - b.1;
+    let alift = asx * asx + asy * asy;
+    let blift = bsx * bsx + bsy * bsy;
+
+    let det = blift * asy - alift * bsy;
+    let permanent = blift * asy.abs() + alift * bsy.abs();
+    let errbound = ARC_ERRBOUND_A * permanent;
+    if det > errbound || -det > errbound {
+        return det;
+    }
+
+    compare_arcs_exact(a, b, s)
+}
+
+fn orient2d_exact(a: (f64, f64), b: (f64, f64), c: (f64, f64)) -> f64 {
+    let acx = two_diff(a.0, c.0);
+    let acy = two_diff(a.1, c.1);
+    let bcx = two_diff(b.0, c.0);
+    let bcy = two_diff(b.1, c.1);
+
+    let left = expansion_product(&acx, &bcy);
+    let right = expansion_product(&acy, &bcx);
+    most_significant(&expansion_sum(&left, &negate(&right)))
+}
+
+fn incircle_exact(a: (f64, f64), b: (f64, f64), c: (f64, f64), d: (f64, f64)) -> f64 {
+    let adx = two_diff(a.0, d.0);
+    let ady = two_diff(a.1, d.1);
+    let bdx = two_diff(b.0, d.0);
+    let bdy = two_diff(b.1, d.1);
+    let cdx = two_diff(c.0, d.0);
+    let cdy = two_diff(c.1, d.1);
+
+    let lift = |dx: &[f64], dy: &[f64]| expansion_sum(&expansion_product(dx, dx), &expansion_product(dy, dy));
+    let cross = |x1: &[f64], y1: &[f64], x2: &[f64], y2: &[f64]| {
+        expansion_sum(&expansion_product(x1, y2), &negate(&expansion_product(y1, x2)))
+    };
+
+    let aterm = expansion_product(&lift(&adx, &ady), &cross(&bdx, &bdy, &cdx, &cdy));
+    let bterm = expansion_product(&lift(&bdx, &bdy), &cross(&cdx, &cdy, &adx, &ady));
+    let cterm = expansion_product(&lift(&cdx, &cdy), &cross(&adx, &ady, &bdx, &bdy));
+    most_significant(&expansion_sum(&expansion_sum(&aterm, &bterm), &cterm))
+}
+
+fn compare_arcs_exact(a: (f64, f64), b: (f64, f64), s: (f64, f64)) -> f64 {
+    let asx = two_diff(s.0, a.0);
+    let asy = two_diff(s.1, a.1);
+    let bsx = two_diff(s.0, b.0);
+    let bsy = two_diff(s.1, b.1);
+
+    let lift = |dx: &[f64], dy: &[f64]| expansion_sum(&expansion_product(dx, dx), &expansion_product(dy, dy));
+    let left = expansion_product(&lift(&bsx, &bsy), &asy);
+    let right = expansion_product(&lift(&asx, &asy), &bsy);
+    most_significant(&expansion_sum(&left, &negate(&right)))
+}
+
+// The components of an expansion are sorted by increasing magnitude and don't
+// overlap, so the sign of the whole expansion is the sign of its last component
+fn most_significant(e: &[f64]) -> f64 {
+    match e.last() {
+        Some(value) => *value,
+        None => 0.
+    }
+}
+
+// Computes a + b exactly, as a rounded sum and the error of that rounding
+fn two_sum(a: f64, b: f64) -> (f64, f64) {
+    let x = a + b;
+    let bvirt = x - a;
+    let avirt = x - bvirt;
+    let bround = b - bvirt;
+    let around = a - avirt;
+    (x, around + bround)
+}
+
+// Computes a - b exactly, as an expansion
+fn two_diff(a: f64, b: f64) -> Vec<f64> {
+    let x = a - b;
+    let bvirt = a - x;
+    let avirt = x + bvirt;
+    let bround = bvirt - b;
+    let around = a - avirt;
+    let y = around + bround;
+    if y == 0. { vec![x] } else { vec![y, x] }
+}
+
+fn split(a: f64) -> (f64, f64) {
+    let c = SPLITTER * a;
+    let abig = c - a;
+    let hi = c - abig;
+    (hi, a - hi)
+}
+
+// Computes a * b exactly, as a rounded product and the error of that rounding
+fn two_product(a: f64, b: f64) -> (f64, f64) {
+    let x = a * b;
+    let (ahi, alo) = split(a);
+    let (bhi, blo) = split(b);
+    let err1 = x - ahi * bhi;
+    let err2 = err1 - alo * bhi;
+    let err3 = err2 - ahi * blo;
+    (x, alo * blo - err3)
+}
+
+// Sums two expansions, eliminating zero components
+fn expansion_sum(e: &[f64], f: &[f64]) -> Vec<f64> {
+    let mut result = Vec::with_capacity(e.len() + f.len());
+    let mut merged = Vec::with_capacity(e.len() + f.len());
+    let (mut i, mut j) = (0, 0);
+    while i < e.len() && j < f.len() {
+        if f[j].abs() > e[i].abs() {
+            merged.push(e[i]);
+            i += 1;
+        } else {
+            merged.push(f[j]);
+            j += 1;
+        }
+    }
+    merged.extend_from_slice(&e[i..]);
+    merged.extend_from_slice(&f[j..]);
+
+    let mut q = match merged.first() {
+        Some(value) => *value,
+        None => return result
+    };
+    for value in merged.iter().skip(1) {
+        let (sum, error) = two_sum(q, *value);
+        if error != 0. {
+            result.push(error);
+        }
+        q = sum;
+    }
+    if q != 0. || result.is_empty() {
+        result.push(q);
+    }
+    result
+}
+
+// Multiplies an expansion by a single float, eliminating zero components
+fn scale_expansion(e: &[f64], b: f64) -> Vec<f64> {
+    let mut result = Vec::with_capacity(e.len() * 2);
+    let mut q = 0.;
+    for (i, value) in e.iter().enumerate() {
+        let (product, product_error) = two_product(*value, b);
+        if i == 0 {
+            if product_error != 0. {
+                result.push(product_error);
+            }
+            q = product;
+            continue;
+        }
+        let (sum, sum_error) = two_sum(q, product_error);
+        if sum_error != 0. {
+            result.push(sum_error);
+        }
+        let (new_q, error) = two_sum(product, sum);
+        if error != 0. {
+            result.push(error);
+        }
+        q = new_q;
+    }
+    if q != 0. || result.is_empty() {
+        result.push(q);
+    }
+    result
+}
+
+fn expansion_product(e: &[f64], f: &[f64]) -> Vec<f64> {
+    let mut result = vec![0.];
+    for value in f {
+        result = expansion_sum(&result, &scale_expansion(e, *value));
+    }
+    result
+}
+
+fn negate(e: &[f64]) -> Vec<f64> {
+    e.iter().map(|value| -value).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::predicates::{orient2d, incircle, compare_arcs};
+
+    #[test]
+    fn it_orients_nearly_collinear_points() {
+        assert!(orient2d((0., 0.), (1., 0.), (0., 1.)) > 0.);
+        assert!(orient2d((0., 0.), (0., 1.), (1., 0.)) < 0.);
+        assert_eq!(orient2d((0., 0.), (1., 1.), (2., 2.)), 0.);
+
+        // These points are exactly collinear, but the naive determinant isn't zero
+        assert_eq!(orient2d((0.1, 0.1), (0.3, 0.3), (1e16 + 0.5, 1e16 + 0.5)), 0.);
+
+        // Nudge a point off the line y = x by the smallest possible amount, where
+        // the naive determinant is dominated by rounding error
+        let above = f64::from_bits(12.0_f64.to_bits() + 1);
+        let below = f64::from_bits(12.0_f64.to_bits() - 1);
+        assert_eq!(orient2d((24., 24.), (0.5, 0.5), (12., 12.)), 0.);
+        assert!(orient2d((24., 24.), (0.5, 0.5), (12., above)) < 0.);
+        assert!(orient2d((24., 24.), (0.5, 0.5), (12., below)) > 0.);
+    }
+
+    #[test]
+    fn it_tests_points_against_circles() {
+        assert!(incircle((0., 0.), (1., 0.), (0., 1.), (0.2, 0.2)) > 0.);
+        assert!(incircle((0., 0.), (1., 0.), (0., 1.), (2., 2.)) < 0.);
+        assert_eq!(incircle((0., 0.), (1., 0.), (0., 1.), (1., 1.)), 0.);
+
+        // Cocircular grid points far from the origin
+        let offset = 1e8;
+        assert_eq!(incircle((offset, offset), (offset + 1., offset), (offset + 1., offset + 1.), (offset, offset + 1.)), 0.);
+        assert!(incircle((offset, offset), (offset + 1., offset), (offset + 1., offset + 1.), (offset, offset + 1. - 1e-8)) > 0.);
+        assert!(incircle((offset, offset), (offset + 1., offset), (offset + 1., offset + 1.), (offset, offset + 1. + 1e-8)) < 0.);
+    }
+
+    #[test]
+    fn it_compares_arcs() {
+        // The arcs of foci level with each other cross halfway between them
+        assert!(compare_arcs((0., 0.), (2., 0.), (0.5, 1.)) > 0.);
+        assert!(compare_arcs((0., 0.), (2., 0.), (1.5, 1.)) < 0.);
+        assert_eq!(compare_arcs((0., 0.), (2., 0.), (1., 1.)), 0.);
+
+        // A focus on the directrix has an arc that's just a vertical line up to it
+        assert!(compare_arcs((0., 0.), (1., 1.), (3., 1.)) > 0.);
+        assert_eq!(compare_arcs((0., 0.), (1., 1.), (1., 1.)), 0.);
+
+        // The arcs of (0, 0) and (1, 3) cross right below (5, 5), even far from
+        // the origin
+        let offset = 1e9;
+        assert_eq!(compare_arcs((offset, offset), (offset + 1., offset + 3.), (offset + 5., offset + 5.)), 0.);
+        assert!(compare_arcs((offset, offset), (offset + 1., offset + 3.), (offset + 5. - 1e-6, offset + 5.)) < 0.);
+        assert!(compare_arcs((offset, offset), (offset + 1., offset + 3.), (offset + 5. + 1e-6, offset + 5.)) > 0.);
+    }
+}
@@ -0,0 +1,207 @@
+// Lloyd relaxation: moves every site to the centroid of its cell and builds the
+// diagram again, which spreads the sites out evenly. Each step lowers the
+// energy of the diagram (how far the points of each cell are from its site,
+// squared), and the sites settle where each one is at its cell's centroid,
+// i.e. a centroidal Voronoi tessellation. Weighing the cells by a density
+// gathers the sites where it's high instead, like the dots of a stippled
+// picture.
+use crate::clip::ClipRegion;
+use crate::dcel::{Dcel, FaceId};
+use crate::density::Density;
+use crate::error::Result;
+use crate::{Voronoi, InputSite};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Stop {
+    // After this many steps
+    Iterations(usize),
+    // Once no site moves farther than the tolerance in a step, or after the
+    // most steps if that comes first
+    Converged { tolerance: f64, max_iterations: usize }
+}
+
+pub struct Relaxation {
+    // Where the sites ended up, in the same order as they were given
+    pub sites: Vec<InputSite>,
+    // The diagram of the sites where they ended up, clipped to the region
+    pub diagram: Dcel,
+    // The energy of the diagram before each step, and then of the last one
+    pub energies: Vec<f64>,
+    pub iterations: usize,
+    pub converged: bool
+}
+
+// Where a cell's centroid is, and its energy: the integral over it of the
+// squared distance to its site, weighted by the density if there is one
+struct Mass {
+    centroid: (f64, f64),
+    energy: f64
+}
+
+// Relaxes the sites within a region. Sites whose cells are outside of it stay
+// where they are.
+pub fn relax(sites: Vec<InputSite>, region: &dyn ClipRegion, stop: Stop) -> Result<Relaxation> {
+    relax_by(sites, region, stop, |diagram, face| {
+        // The energy is the second moment about the centroid, plus the area
+        // times the squared distance from the centroid to the site
+        let cell = diagram.cell_metrics(face)?;
+        let (x, y) = diagram.site(face);
+        let (dx, dy) = (cell.centroid.0 - x, cell.centroid.1 - y);
+        Some(Mass { centroid: cell.centroid, energy: cell.second_moment + cell.area * (dx * dx + dy * dy) })
+    })
+}
+
+// Like `relax`, but moves the sites to the centroids of their cells weighted
+// by a density. Sites whose cells have no density in them stay where they
+// are too.
+pub fn relax_with_density(sites: Vec<InputSite>, region: &dyn ClipRegion, density: &dyn Density, stop: Stop) -> Result<Relaxation> {
+    relax_by(sites, region, stop, |diagram, face| weighted_mass(diagram, face, density))
+}
+
+fn relax_by(sites: Vec<InputSite>, region: &dyn ClipRegion, stop: Stop, mass: impl Fn(&Dcel, FaceId) -> Option<Mass>) -> Result<Relaxation> {
+    let (max_iterations, tolerance) = match stop {
+        Stop::Iterations(iterations) => (iterations, None),
+        Stop::Converged { tolerance, max_iterations } => (max_iterations, Some(tolerance))
+    };
+
+    let mut positions: Vec<(f64, f64)> = sites.iter().map(|site| (site.x, site.y)).collect();
+    let mut energies = Vec::new();
+    let mut iterations = 0;
+    let mut converged = false;
+    loop {
+        let sites = positions.iter().map(|&(x, y)| InputSite { x, y }).collect();
+        let diagram = Voronoi::new(sites, 0., 0., 0., 0.).try_run_unclipped()?.clip(region)?;
+        let masses: Vec<Option<Mass>> = diagram.faces().map(|face| mass(&diagram, face)).collect();
+        energies.push(masses.iter().flatten().map(|mass| mass.energy).sum());
+        if converged || iterations == max_iterations {
+            return Ok(Relaxation {
+                sites: positions.into_iter().map(|(x, y)| InputSite { x, y }).collect(),
+                diagram,
+                energies,
+                iterations,
+                converged
+            });
+        }
+
+        let mut farthest: f64 = 0.;
+        for (site, mass) in positions.iter_mut().zip(&masses) {
+            if let Some(Mass { centroid, .. }) = mass {
+                farthest = farthest.max((centroid.0 - site.0).hypot(centroid.1 - site.1));
+                *site = *centroid;
+            }
+        }
+        iterations += 1;
+        converged = tolerance.is_some_and(|tolerance| farthest <= tolerance);
+    }
+}
+
+// Integrates a density over a cell, as triangles between the site and each
+// edge (or chord of an arc). Triangles of holes come out negative, so that
+// they're taken away.
+fn weighted_mass(diagram: &Dcel, face: FaceId, density: &dyn Density) -> Option<Mass> {
+    let halfedges: Vec<_> = diagram.face_halfedges(face).collect();
+    if halfedges.is_empty() || halfedges.iter().any(|halfedge| diagram.next(*halfedge).is_none()) {
+        return None;
+    }
+
+    let site = diagram.site(face);
+    let mut integrals = [0.; 4];
+    for halfedge in halfedges {
+        let mut points = vec![diagram.position(diagram.origin(halfedge)?)];
+        if let Some(arc) = diagram.arc(halfedge) {
+            points.extend(diagram.get_chords(halfedge.index(), arc));
+        }
+        points.push(diagram.position(diagram.origin(diagram.twin(halfedge))?));
+        for pair in points.windows(2) {
+            integrate_triangle(site, pair[0], pair[1], density, &mut integrals);
+        }
+    }
+
+    let [mass, x, y, squared] = integrals;
+    if mass.is_nan() || mass <= 0. {
+        return None;
+    }
+    Some(Mass { centroid: (site.0 + x / mass, site.1 + y / mass), energy: squared })
+}
+
+// Adds the integrals of the density over the triangle between the site and
+// the points a and b, and of the density times x, y and the squared distance
+// relative to the site. The triangle is split into pieces about as big as the
+// density's details, and each is sampled at the middle of its sides, which
+// gets the mass and centroid exactly where the density is linear.
+fn integrate_triangle(site: (f64, f64), a: (f64, f64), b: (f64, f64), density: &dyn Density, integrals: &mut [f64; 4]) {
+    let (ax, ay) = (a.0 - site.0, a.1 - site.1);
+    let (bx, by) = (b.0 - site.0, b.1 - site.1);
+    let area = (ax * by - bx * ay) / 2.;
+    if area == 0. {
+        return;
+    }
+    let longest = ax.hypot(ay).max(bx.hypot(by)).max((bx - ax).hypot(by - ay));
+    let pieces = match density.resolution() {
+        Some(resolution) if resolution > 0. => (longest / resolution).ceil().clamp(1., 1000.) as usize,
+        _ => 4
+    };
+
+    let count = pieces as f64;
+    let point = |i: usize, j: usize| {
+        let (s, t) = (i as f64 / count, j as f64 / count);
+        (ax * s + bx * t, ay * s + by * t)
+    };
+    let weight = area / (count * count) / 3.;
+    let mut sample = |(x1, y1): (f64, f64), (x2, y2): (f64, f64)| {
+        let (x, y) = ((x1 + x2) / 2., (y1 + y2) / 2.);
+        let mass = density.density(site.0 + x, site.1 + y) * weight;
+        integrals[0] += mass;
+        integrals[1] += mass * x;
+        integrals[2] += mass * y;
+        integrals[3] += mass * (x * x + y * y);
+    };
+    for i in 0..pieces {
+        for j in 0..pieces - i {
+            let (p, q, r) = (point(i, j), point(i + 1, j), point(i, j + 1));
+            sample(p, q);
+            sample(q, r);
+            sample(r, p);
+            if i + j + 1 < pieces {
+                let s = point(i + 1, j + 1);
+                sample(q, s);
+                sample(s, r);
+                sample(r, q);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{InputSite, BoundingBox, Circle};
+    use crate::relax::{relax, Stop};
+    use rand::prelude::*;
+
+    #[test]
+    fn it_relaxes_sites() {
+        let mut rng = StdRng::seed_from_u64(6);
+        let sites: Vec<InputSite> = (0..50).map(|_| InputSite { x: rng.gen::<f64>(), y: rng.gen::<f64>() }).collect();
+        let region = BoundingBox::new(0., 0., 1., 1.);
+        let relaxation = relax(sites, &region, Stop::Converged { tolerance: 1e-4, max_iterations: 1000 }).unwrap();
+        assert!(relaxation.converged);
+        assert_eq!(relaxation.sites.len(), 50);
+        assert_eq!(relaxation.energies.len(), relaxation.iterations + 1);
+        assert!(relaxation.energies.windows(2).all(|pair| pair[1] <= pair[0] + 1e-12));
+
+        // Every site ends up at the centroid of its cell
+        for (site, cell) in relaxation.sites.iter().zip(relaxation.diagram.get_cell_metrics()) {
+            let cell = cell.unwrap();
+            assert!((cell.centroid.0 - site.x).hypot(cell.centroid.1 - site.y) < 1e-3);
+        }
+
+        // Sites keep their order, and ones outside the region stay put
+        let sites = vec![InputSite { x: 0.2, y: 0.3 }, InputSite { x: 5., y: 5. }, InputSite { x: 0.9, y: 0.9 }];
+        let relaxation = relax(sites, &Circle::new(0.5, 0.5, 0.5), Stop::Iterations(3)).unwrap();
+        assert_eq!(relaxation.iterations, 3);
+        assert!(!relaxation.converged);
+        assert_eq!((relaxation.sites[1].x, relaxation.sites[1].y), (5., 5.));
+        assert!(relaxation.sites[0].x < 0.5 && relaxation.sites[2].x > 0.5);
+        assert!(relaxation.diagram.cell_metrics(relaxation.diagram.faces().nth(1).unwrap()).is_none());
+    }
+}